@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: chip8-asm <source.8o> [-o <output.ch8>]";
+
+fn main() -> ExitCode {
+    let mut source_path = None;
+    let mut output_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let Some(path) = args.next() else {
+                    eprintln!("{} needs a path\n{}", arg, USAGE);
+                    return ExitCode::FAILURE;
+                };
+                output_path = Some(PathBuf::from(path));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if source_path.is_none() => source_path = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let Some(source_path) = source_path else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    let output_path = output_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+
+    let source = match std::fs::read_to_string(&source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Error: could not read {}: {}", source_path.display(), error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let rom = match chip8::asm::assemble(&source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}:{}", source_path.display(), error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(error) = std::fs::write(&output_path, rom) {
+        eprintln!(
+            "Error: could not write {}: {}",
+            output_path.display(),
+            error
+        );
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,146 @@
+// Encoders for the screen, each pixel being a mask of the planes lit there
+
+const PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 161, 0], [255, 203, 0]];
+const ASCII: [char; 4] = ['.', '#', '+', '@'];
+
+pub fn ascii(rows: &[Vec<u8>]) -> String {
+    let mut text = String::new();
+    for row in rows {
+        text.extend(row.iter().map(|&pixel| ASCII[pixel as usize & 0b11]));
+        text.push('\n');
+    }
+    text
+}
+
+/// Binary PBM, pixels lit on any plane are black
+pub fn pbm(rows: &[Vec<u8>]) -> Vec<u8> {
+    let width = rows.first().map_or(0, |row| row.len());
+    let mut image = format!("P4\n{} {}\n", width, rows.len()).into_bytes();
+    for row in rows {
+        for byte in row.chunks(8) {
+            let bits = byte.iter().enumerate().fold(0, |bits, (bit, &pixel)| {
+                bits | ((pixel != 0) as u8) << (7 - bit)
+            });
+            image.push(bits);
+        }
+    }
+    image
+}
+
+/// Indexed colour PNG with the frontend's plane colours. The image data is
+/// stored uncompressed, the screen is at most 8 KiB.
+pub fn png(rows: &[Vec<u8>]) -> Vec<u8> {
+    let width = rows.first().map_or(0, |row| row.len()) as u32;
+
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend((rows.len() as u32).to_be_bytes());
+    // 8 bits per pixel, indexed colour, default compression, filter and interlacing
+    header.extend([8, 3, 0, 0, 0]);
+
+    let mut scanlines = Vec::new();
+    for row in rows {
+        scanlines.push(0);
+        scanlines.extend(row.iter().map(|pixel| pixel & 0b11));
+    }
+
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut image, b"IHDR", &header);
+    write_chunk(&mut image, b"PLTE", &PALETTE.concat());
+    write_chunk(&mut image, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+fn write_chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend((data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+    let crc = crc32(&image[start..]);
+    image.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend(len.to_le_bytes());
+        stream.extend((!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1_u32, 0_u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(
+            zlib_stored(b"abc"),
+            [0x78, 0x01, 1, 3, 0, 0xFC, 0xFF, b'a', b'b', b'c', 0x02, 0x4D, 0x01, 0x27]
+        );
+        let long = zlib_stored(&[0; 0x10000]);
+        assert_eq!(&long[2..7], [0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(&long[7 + 0xFFFF..7 + 0xFFFF + 5], [1, 1, 0, 0xFE, 0xFF]);
+    }
+
+    #[test]
+    fn test_pbm() {
+        let rows = [vec![1, 0, 2, 0, 0, 0, 0, 3, 1], vec![0; 9]];
+        assert_eq!(pbm(&rows), b"P4\n9 2\n\xA1\x80\x00\x00");
+    }
+
+    #[test]
+    fn test_png() {
+        let image = png(&[vec![0, 1, 2, 3]]);
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(
+            &image[8..33],
+            b"\0\0\0\x0DIHDR\0\0\0\x04\0\0\0\x01\x08\x03\0\0\0\xCE\xE2\xFF\xFF"
+        );
+        assert_eq!(&image[image.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+    }
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(ascii(&[vec![0, 1, 2, 3]]), ".#+@\n");
+    }
+}
@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chip8::{
+    InstructionSet, LoadOptions, Platform, Quirks, SeededRandom, StepOutcome, VirtualMachine,
+};
+
+mod image;
+
+const USAGE: &str = "Usage: chip8-headless <rom> [options]
+
+Options:
+  --frames N              Frames to run, 60 a second (default 60)
+  --ipf N                 Instructions per frame (default 20, or the platform's)
+  --platform vip|chip48|schip-legacy|schip|xo
+                          Platform preset: instruction set, quirks, speed, font
+                          and stack depth, the options below override it
+  --set chip8|schip|xo    Instruction set (default chip8)
+  --quirks vip|chip48|schip
+                          Quirk preset (default: this interpreter's defaults)
+  --seed N                Seed for CXNN
+  --load-address HEX      Address the ROM is loaded at (default 200)
+  --entry HEX             Address execution starts at (default: the load address)
+  --press FRAME:KEY[:N]   Hold hex KEY for N frames from FRAME (default 1), repeatable
+  --output PATH           Screenshot to write after the last frame
+  --format png|pbm|ascii  Screenshot format (default: from the extension, else ascii)
+
+Without --output the screen is printed as ASCII art.";
+
+const DEFAULT_FRAMES: u32 = 60;
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Pbm,
+    Ascii,
+}
+
+struct KeyPress {
+    frame: u32,
+    key: u8,
+    frames: u32,
+}
+
+struct Options {
+    rom_path: PathBuf,
+    frames: u32,
+    instructions_per_frame: Option<u32>,
+    platform: Option<Platform>,
+    instruction_set: Option<InstructionSet>,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    load_address: u16,
+    entry_point: Option<u16>,
+    presses: Vec<KeyPress>,
+    output: Option<PathBuf>,
+    format: Option<Format>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("Error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match std::fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!(
+                "Error: could not read {}: {}",
+                options.rom_path.display(),
+                error
+            );
+            return ExitCode::from(2);
+        }
+    };
+
+    let rng = options
+        .seed
+        .map_or_else(SeededRandom::default, SeededRandom::new);
+    let mut vm = VirtualMachine::with_random_source(InstructionSet::Chip8, Quirks::default(), rng);
+    if let Some(platform) = options.platform {
+        vm.set_platform(platform);
+    }
+    if let Some(instruction_set) = options.instruction_set {
+        vm.set_instruction_set(instruction_set);
+    }
+    if let Some(quirks) = options.quirks {
+        vm.set_quirks(quirks);
+    }
+    let instructions_per_frame = options.instructions_per_frame.unwrap_or_else(|| {
+        options.platform.map_or(
+            DEFAULT_INSTRUCTIONS_PER_FRAME,
+            Platform::instructions_per_frame,
+        )
+    });
+    let load = LoadOptions {
+        load_address: options.load_address,
+        entry_point: options.entry_point.unwrap_or(options.load_address),
+    };
+    if let Err(error) = vm.load_rom_with(&rom, load) {
+        eprintln!(
+            "Error: could not load {}: {}",
+            options.rom_path.display(),
+            error
+        );
+        return ExitCode::from(2);
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    'frames: for frame in 0..options.frames {
+        for key in 0..16 {
+            let is_down = options.presses.iter().any(|press| {
+                press.key == key
+                    && (press.frame..press.frame.saturating_add(press.frames)).contains(&frame)
+            });
+            // FX0A completes when a key is released, as in the frontend
+            if vm.blocked_on_key_press && vm.key_state[key as usize] && !is_down {
+                vm.complete_fx0a(key);
+            }
+            vm.key_state[key as usize] = is_down;
+        }
+
+        for _ in 0..instructions_per_frame {
+            match vm.execute_instruction() {
+                Ok(StepOutcome::Executed) => {}
+                Ok(StepOutcome::Exited) => break 'frames,
+                Ok(_) => break,
+                Err(error) => {
+                    eprintln!("Error: {} in frame {}", error, frame);
+                    status = ExitCode::FAILURE;
+                    break 'frames;
+                }
+            }
+        }
+
+        vm.decrement_timers();
+    }
+
+    let rows: Vec<Vec<u8>> = vm.screen_rows().map(|row| row.to_vec()).collect();
+    let Some(output) = options.output else {
+        print!("{}", image::ascii(&rows));
+        return status;
+    };
+
+    let format = options.format.unwrap_or_else(|| {
+        match output.extension().and_then(|extension| extension.to_str()) {
+            Some("png") => Format::Png,
+            Some("pbm") => Format::Pbm,
+            _ => Format::Ascii,
+        }
+    });
+    let image = match format {
+        Format::Png => image::png(&rows),
+        Format::Pbm => image::pbm(&rows),
+        Format::Ascii => image::ascii(&rows).into_bytes(),
+    };
+    if let Err(error) = std::fs::write(&output, image) {
+        eprintln!("Error: could not write {}: {}", output.display(), error);
+        return ExitCode::from(2);
+    }
+
+    status
+}
+
+/// The options, or `None` when usage was asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        instructions_per_frame: None,
+        platform: None,
+        instruction_set: None,
+        quirks: None,
+        seed: None,
+        load_address: chip8::PROGAM_COUNTER_START,
+        entry_point: None,
+        presses: Vec::new(),
+        output: None,
+        format: None,
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+        if !arg.starts_with("--") {
+            if rom_path.replace(PathBuf::from(&arg)).is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--frames" => options.frames = number(&value)?,
+            "--ipf" => options.instructions_per_frame = Some(number(&value)?),
+            "--seed" => options.seed = Some(number(&value)?),
+            "--load-address" => options.load_address = address(&value)?,
+            "--entry" => options.entry_point = Some(address(&value)?),
+            "--platform" => {
+                options.platform = Some(match value.as_str() {
+                    "vip" => Platform::CosmacVip,
+                    "chip48" => Platform::Chip48,
+                    "schip-legacy" => Platform::SuperChipLegacy,
+                    "schip" => Platform::SuperChipModern,
+                    "xo" => Platform::XoChip,
+                    _ => return Err(format!("unknown platform {}", value)),
+                })
+            }
+            "--set" => {
+                options.instruction_set = Some(match value.as_str() {
+                    "chip8" => InstructionSet::Chip8,
+                    "schip" => InstructionSet::SuperChip,
+                    "xo" => InstructionSet::XoChip,
+                    _ => return Err(format!("unknown instruction set {}", value)),
+                })
+            }
+            "--quirks" => {
+                options.quirks = Some(match value.as_str() {
+                    "vip" => Quirks::COSMAC_VIP,
+                    "chip48" => Quirks::CHIP_48,
+                    "schip" => Quirks::SUPER_CHIP,
+                    _ => return Err(format!("unknown quirk preset {}", value)),
+                })
+            }
+            "--press" => options.presses.push(key_press(&value)?),
+            "--output" => options.output = Some(PathBuf::from(value)),
+            "--format" => {
+                options.format = Some(match value.as_str() {
+                    "png" => Format::Png,
+                    "pbm" => Format::Pbm,
+                    "ascii" => Format::Ascii,
+                    _ => return Err(format!("unknown format {}", value)),
+                })
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(Some(options))
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", value))
+}
+
+fn address(value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value, 16).map_err(|_| format!("{} is not a hex address", value))
+}
+
+fn key_press(value: &str) -> Result<KeyPress, String> {
+    let mut parts = value.split(':');
+    let (Some(frame), Some(key)) = (parts.next(), parts.next()) else {
+        return Err(format!("{} is not FRAME:KEY[:N]", value));
+    };
+    let key = u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|&key| key < 16)
+        .ok_or_else(|| format!("{} is not a key from 0 to F", key))?;
+
+    Ok(KeyPress {
+        frame: number(frame)?,
+        key,
+        frames: parts.next().map_or(Ok(1), number)?,
+    })
+}
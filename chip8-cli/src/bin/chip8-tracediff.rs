@@ -0,0 +1,94 @@
+use std::process::ExitCode;
+
+use chip8::divergence::{compare_entries, compare_vms, first_divergence, split_states};
+use chip8::trace::read_trace;
+use chip8::VirtualMachine;
+
+const USAGE: &str = "Usage: chip8-tracediff <left> <right>
+
+Both files are execution traces, text or binary, or both are streams of save
+states written back to back. Exits with 1 if they differ.
+
+At the first difference traces show the registers, timers, stack and the
+memory the instruction wrote. State streams show all memory that differs.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [left_path, right_path] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let read = |path: &String| {
+        std::fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))
+    };
+    let report = read(left_path).and_then(|left| {
+        let right = read(right_path)?;
+        if left.starts_with(b"C8SS") || right.starts_with(b"C8SS") {
+            diff_states(&left, &right)
+        } else {
+            diff_traces(&left, &right)
+        }
+    });
+
+    match report {
+        Ok(None) => {
+            println!("No divergence");
+            ExitCode::SUCCESS
+        }
+        Ok(Some(report)) => {
+            print!("{}", report);
+            ExitCode::FAILURE
+        }
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn diff_traces(left: &[u8], right: &[u8]) -> Result<Option<String>, String> {
+    let left = read_trace(left).map_err(|error| format!("left trace: {}", error))?;
+    let right = read_trace(right).map_err(|error| format!("right trace: {}", error))?;
+
+    let Some(index) = first_divergence(&left, &right) else {
+        return Ok(None);
+    };
+    let mut report = format!("Traces diverge at instruction {}\n", index);
+    match (left.get(index), right.get(index)) {
+        (Some(left), Some(right)) => {
+            report += &compare_entries(left, right);
+        }
+        (Some(_), None) => report += "The right trace ends here\n",
+        _ => report += "The left trace ends here\n",
+    }
+
+    Ok(Some(report))
+}
+
+fn diff_states(left: &[u8], right: &[u8]) -> Result<Option<String>, String> {
+    let left = split_states(left).map_err(|error| format!("left states: {}", error))?;
+    let right = split_states(right).map_err(|error| format!("right states: {}", error))?;
+
+    let Some(index) = first_divergence(&left, &right) else {
+        return Ok(None);
+    };
+    let mut report = format!("States diverge at state {}\n", index);
+    match (left.get(index), right.get(index)) {
+        (Some(left), Some(right)) => {
+            let mut left_vm = VirtualMachine::new();
+            let mut right_vm = VirtualMachine::new();
+            left_vm
+                .load_state(left)
+                .map_err(|error| format!("left state {}: {}", index, error))?;
+            right_vm
+                .load_state(right)
+                .map_err(|error| format!("right state {}: {}", index, error))?;
+            report += &compare_vms(&left_vm, &right_vm);
+        }
+        (Some(_), None) => report += "The right stream ends here\n",
+        _ => report += "The left stream ends here\n",
+    }
+
+    Ok(Some(report))
+}
@@ -1,4 +1,5 @@
-use chip8::VirtualMachine;
+use chip8::database::Database;
+use chip8::{InstructionSet, Platform, RandomSource, Rewind, StepOutcome, VirtualMachine, VmError};
 #[cfg(feature = "profile")]
 use egui::containers::Window;
 use egui::containers::{collapsing_header::CollapsingHeader, TopBottomPanel};
@@ -9,8 +10,12 @@ use std::sync::{Arc, Mutex};
 
 mod future_util;
 mod input_mapping;
+mod rom_settings;
+mod save_slots;
 use future_util::NoWakeFuture;
 use input_mapping::{KeyValue, ACCEPTED_KEYS};
+use rom_settings::RomSettings;
+use save_slots::{SaveSlots, SLOT_COUNT};
 
 const SCALE_FACTOR: u32 = 24;
 const WINDOW_SIZE: (i32, i32) = (
@@ -20,6 +25,13 @@ const WINDOW_SIZE: (i32, i32) = (
 const TARGET_FPS: u32 = 60;
 const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 20;
 const TARGET_MS_PER_FRAME: f64 = 1.0 / TARGET_FPS as f64;
+const REWIND_SECONDS: u32 = 300;
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+const INSTRUCTION_SETS: [(InstructionSet, &str); 3] = [
+    (InstructionSet::Chip8, "CHIP-8"),
+    (InstructionSet::SuperChip, "SUPER-CHIP"),
+    (InstructionSet::XoChip, "XO-CHIP"),
+];
 
 fn window_conf() -> Conf {
     Conf {
@@ -34,18 +46,20 @@ fn handle_key_event(vm: &mut VirtualMachine, keycode: KeyCode, is_down: bool) {
     let value_result: Result<KeyValue, _> = KeyValue::try_from(keycode);
 
     match value_result {
-        Ok(key_value) => {
-            if vm.blocked_on_key_press && vm.key_state[key_value.0 as usize] && !is_down {
-                vm.complete_fx0a(key_value.0);
-            }
-
-            vm.key_state[key_value.0 as usize] = is_down;
-        }
+        Ok(key_value) => set_key(vm, key_value.0, is_down),
         Err(message) => eprintln!("Error: {}", message),
     }
 }
 
-fn check_keys(vm: &mut VirtualMachine) {
+fn set_key(vm: &mut VirtualMachine, key: u8, is_down: bool) {
+    if vm.blocked_on_key_press && vm.key_state[key as usize] && !is_down {
+        vm.complete_fx0a(key);
+    }
+
+    vm.key_state[key as usize] = is_down;
+}
+
+fn check_keys(vm: &mut VirtualMachine, bindings: &[(KeyCode, u8)]) {
     #[cfg(feature = "profile")]
     puffin::profile_function!();
     for keycode in ACCEPTED_KEYS {
@@ -55,6 +69,32 @@ fn check_keys(vm: &mut VirtualMachine) {
             handle_key_event(vm, keycode, false);
         }
     }
+    for &(keycode, key) in bindings {
+        if is_key_down(keycode) {
+            set_key(vm, key, true);
+        } else if is_key_released(keycode) {
+            set_key(vm, key, false);
+        }
+    }
+}
+
+/// The database at `$CHIP8_DATABASE`, such as the full upstream
+/// `programs.json`, or the built in one
+fn load_database() -> Database {
+    let Some(path) = std::env::var_os("CHIP8_DATABASE") else {
+        return Database::builtin();
+    };
+    let loaded = std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|json| Database::from_json(&json).map_err(|error| error.to_string()));
+    loaded.unwrap_or_else(|error| {
+        eprintln!(
+            "Could not read the ROM database {}: {}",
+            path.to_string_lossy(),
+            error
+        );
+        Database::builtin()
+    })
 }
 
 #[macroquad::main(window_conf)]
@@ -63,14 +103,28 @@ async fn main() {
     puffin::set_scopes_on(true);
 
     let mut vm = chip8::VirtualMachine::new();
+    // CXNN is reproducible for a given seed, so seed from the clock to vary each session
+    vm.random_source_mut()
+        .set_state(macroquad::miniquad::date::now().to_bits());
+    let database = Arc::new(load_database());
     let rom = include_bytes!("../../assets/chip8-test-suite.ch8");
-    vm.load_rom(rom);
+    let info = vm
+        .load_rom_with_database(rom, &database)
+        .expect("the test suite fits in memory");
+    let mut settings = RomSettings::new(info);
 
     let vm = Arc::new(Mutex::new(vm));
+    // Filled in by the Load Rom coroutine, applied at the start of a frame
+    let loaded_settings: Arc<Mutex<Option<RomSettings>>> = Arc::new(Mutex::new(None));
 
-    let mut instructions_per_frame = DEFAULT_INSTRUCTIONS_PER_FRAME;
+    let mut instructions_per_frame = settings.instructions_per_frame;
+    let mut fault: Option<VmError> = None;
+    let mut save_slots = SaveSlots::new();
+    let mut rewind = Rewind::new((REWIND_SECONDS * TARGET_FPS) as usize);
     let mut previous = get_time();
     let mut lag = 0.0;
+    let mut screen_image = Image::empty();
+    let mut screen_texture = Texture2D::empty();
 
     loop {
         #[cfg(feature = "profile")]
@@ -81,7 +135,16 @@ async fn main() {
         previous = current;
         lag += elapsed;
 
-        check_keys(&mut vm.lock().unwrap());
+        let loaded = loaded_settings.lock().unwrap().take();
+        if let Some(loaded) = loaded {
+            // A new ROM is in the VM, so the history of the old one is stale
+            fault = None;
+            rewind.clear();
+            instructions_per_frame = loaded.instructions_per_frame;
+            settings = loaded;
+        }
+
+        check_keys(&mut vm.lock().unwrap(), &settings.bindings);
 
         {
             #[cfg(feature = "profile")]
@@ -90,11 +153,30 @@ async fn main() {
             let mut v = vm.lock().unwrap();
             while lag >= TARGET_MS_PER_FRAME {
                 lag -= TARGET_MS_PER_FRAME;
+                if is_key_down(REWIND_KEY) {
+                    if rewind.step_back(&mut v) {
+                        fault = None;
+                    }
+                    continue;
+                }
+                if fault.is_some() {
+                    continue;
+                }
+
                 for _ in 0..instructions_per_frame {
-                    v.execute_instruction();
+                    match v.execute_instruction() {
+                        Ok(StepOutcome::Executed) => {}
+                        Ok(_) => break,
+                        Err(error) => {
+                            eprintln!("Error: {}", error);
+                            fault = Some(error);
+                            break;
+                        }
+                    }
                 }
 
                 v.decrement_timers();
+                rewind.push(&v);
             }
         }
 
@@ -105,20 +187,110 @@ async fn main() {
                 .resizable(false)
                 .show(ctx, |ui| {
                     CollapsingHeader::new("Config").show(ui, |ui| {
-                        let slider =
-                            egui::widgets::Slider::new(&mut instructions_per_frame, 5..=500)
-                                .text("Instructions Per Frame");
-                        ui.add(slider);
+                        if let Some(description) = &settings.description {
+                            ui.label(description);
+                        }
+
+                        ui.horizontal(|ui| {
+                            let slider =
+                                egui::widgets::Slider::new(&mut instructions_per_frame, 5..=500)
+                                    .text("Instructions Per Frame");
+                            ui.add(slider);
+
+                            let current = vm.lock().unwrap().platform();
+                            let mut selected = current;
+                            egui::ComboBox::from_label("Platform")
+                                .selected_text(current.map_or("Custom", Platform::name))
+                                .show_ui(ui, |ui| {
+                                    for platform in Platform::ALL {
+                                        ui.selectable_value(
+                                            &mut selected,
+                                            Some(platform),
+                                            platform.name(),
+                                        );
+                                    }
+                                });
+                            if let Some(platform) = selected.filter(|_| selected != current) {
+                                vm.lock().unwrap().set_platform(platform);
+                                instructions_per_frame = platform.instructions_per_frame();
+                            }
+                        });
+
+                        let current = vm.lock().unwrap().instruction_set();
+                        let mut selected = current;
+                        egui::ComboBox::from_label("Instruction Set")
+                            .selected_text(
+                                INSTRUCTION_SETS
+                                    .iter()
+                                    .find(|(set, _)| *set == current)
+                                    .map_or("", |(_, name)| name),
+                            )
+                            .show_ui(ui, |ui| {
+                                for (set, name) in INSTRUCTION_SETS {
+                                    ui.selectable_value(&mut selected, set, name);
+                                }
+                            });
+                        if selected != current {
+                            vm.lock().unwrap().set_instruction_set(selected);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label("Save Slots");
+                            for slot in 0..SLOT_COUNT {
+                                if ui.button(format!("Save {}", slot + 1)).clicked() {
+                                    save_slots.store(slot, vm.lock().unwrap().save_state());
+                                }
+
+                                let load = egui::Button::new(format!("Load {}", slot + 1));
+                                if ui
+                                    .add_enabled(save_slots.get(slot).is_some(), load)
+                                    .clicked()
+                                {
+                                    if let Some(state) = save_slots.get(slot) {
+                                        match vm.lock().unwrap().load_state(state) {
+                                            Ok(()) => fault = None,
+                                            Err(error) => eprintln!("Error: {}", error),
+                                        }
+                                    }
+                                }
+                            }
+                        });
+
+                        if let Some(error) = &fault {
+                            ui.colored_label(egui::Color32::RED, error.to_string());
+                        }
+
+                        ui.label(format!(
+                            "Hold Backspace to rewind ({} s recorded, {} KiB)",
+                            rewind.len() as u32 / TARGET_FPS,
+                            rewind.memory_usage() / 1024
+                        ));
 
                         if ui.button("Load Rom").clicked() {
                             let vm_clone = vm.clone();
+                            let database = database.clone();
+                            let loaded_settings = loaded_settings.clone();
                             let fut = async move {
                                 if let Some(file_handle) =
                                     rfd::AsyncFileDialog::new().pick_file().await
                                 {
                                     let bytes = file_handle.read().await;
 
-                                    vm_clone.lock().unwrap().load_rom(&bytes);
+                                    let loaded = vm_clone
+                                        .lock()
+                                        .unwrap()
+                                        .load_rom_with_database(&bytes, &database);
+                                    match loaded {
+                                        Ok(info) => {
+                                            *loaded_settings.lock().unwrap() =
+                                                Some(RomSettings::new(info));
+                                        }
+                                        Err(error) => eprintln!(
+                                            "Could not load {}: {}",
+                                            file_handle.file_name(),
+                                            error
+                                        ),
+                                    }
                                 }
                             };
 
@@ -134,17 +306,41 @@ async fn main() {
             });
         });
 
-        for (y, row) in (0u32..).zip(vm.lock().unwrap().screen_rows()) {
-            for (x, _) in (0u32..).zip(row).filter(|(_, p)| **p != 0) {
-                draw_rectangle(
-                    (x * SCALE_FACTOR) as f32,
-                    (y * SCALE_FACTOR) as f32,
-                    SCALE_FACTOR as f32,
-                    SCALE_FACTOR as f32,
-                    WHITE,
-                );
+        // The texture is only touched where the screen changed since the last frame
+        {
+            let mut v = vm.lock().unwrap();
+            if let Some(dirty) = v.take_frame() {
+                let size = (v.screen_width() as u16, v.screen_height() as u16);
+                if (screen_image.width, screen_image.height) != size {
+                    screen_texture.delete();
+                    screen_image = Image::gen_image_color(size.0, size.1, BLACK);
+                    screen_texture = Texture2D::from_image(&screen_image);
+                    screen_texture.set_filter(FilterMode::Nearest);
+                }
+
+                for y in dirty.y..dirty.y + dirty.height {
+                    for x in dirty.x..dirty.x + dirty.width {
+                        let pixel = v.pixel(x, y) as usize;
+                        screen_image.set_pixel(
+                            x as u32,
+                            y as u32,
+                            settings.palette[pixel % settings.palette.len()],
+                        );
+                    }
+                }
+                screen_texture.update(&screen_image);
             }
         }
+        draw_texture_ex(
+            screen_texture,
+            0.0,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(WINDOW_SIZE.0 as f32, WINDOW_SIZE.1 as f32)),
+                ..Default::default()
+            },
+        );
 
         {
             #[cfg(feature = "profile")]
@@ -0,0 +1,60 @@
+use chip8::database::RomInfo;
+use macroquad::prelude::{Color, KeyCode, BLACK, GOLD, ORANGE, WHITE};
+
+use crate::DEFAULT_INSTRUCTIONS_PER_FRAME;
+
+// Indexed by which bitplanes a pixel is lit on
+const PLANE_COLORS: [Color; 4] = [BLACK, WHITE, ORANGE, GOLD];
+// Extra keys for the buttons the ROM database names
+const BUTTON_KEYS: [(&str, KeyCode); 6] = [
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("a", KeyCode::Space),
+    ("b", KeyCode::LeftShift),
+];
+
+/// Frontend settings for a loaded ROM, from the ROM database where it was
+/// recognized.
+pub struct RomSettings {
+    pub description: Option<String>,
+    pub instructions_per_frame: u32,
+    pub palette: [Color; 4],
+    pub bindings: Vec<(KeyCode, u8)>,
+}
+
+impl RomSettings {
+    pub fn new(info: Option<&RomInfo>) -> RomSettings {
+        let mut settings = RomSettings {
+            description: None,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            palette: PLANE_COLORS,
+            bindings: Vec::new(),
+        };
+        let Some(info) = info else {
+            return settings;
+        };
+
+        settings.description = Some(if info.authors.is_empty() {
+            info.title.clone()
+        } else {
+            format!("{} by {}", info.title, info.authors.join(", "))
+        });
+        if let Some(tick_rate) = info.tick_rate {
+            settings.instructions_per_frame = tick_rate;
+        }
+        for (color, [r, g, b]) in settings.palette.iter_mut().zip(&info.colors) {
+            *color = Color::from_rgba(*r, *g, *b, 255);
+        }
+        settings.bindings = info
+            .keys
+            .iter()
+            .filter_map(|(button, key)| {
+                let (_, keycode) = BUTTON_KEYS.iter().find(|(name, _)| name == button)?;
+                Some((*keycode, *key))
+            })
+            .collect();
+        settings
+    }
+}
@@ -0,0 +1,47 @@
+pub const SLOT_COUNT: usize = 4;
+
+// Native builds keep slots on disk so they survive restarts and can be copied
+// to other machines, the web build only keeps them for the session
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_DIRECTORY: &str = "saves";
+
+pub struct SaveSlots {
+    slots: [Option<Vec<u8>>; SLOT_COUNT],
+}
+
+impl SaveSlots {
+    pub fn new() -> Self {
+        let mut slots = SaveSlots {
+            slots: Default::default(),
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        for (slot, state) in slots.slots.iter_mut().enumerate() {
+            *state = std::fs::read(Self::path(slot)).ok();
+        }
+
+        slots
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&[u8]> {
+        self.slots[slot].as_deref()
+    }
+
+    pub fn store(&mut self, slot: usize, state: Vec<u8>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let written = std::fs::create_dir_all(SAVE_DIRECTORY)
+                .and_then(|_| std::fs::write(Self::path(slot), &state));
+            if let Err(error) = written {
+                eprintln!("Error: could not write save slot {}: {}", slot + 1, error);
+            }
+        }
+
+        self.slots[slot] = Some(state);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn path(slot: usize) -> std::path::PathBuf {
+        std::path::Path::new(SAVE_DIRECTORY).join(format!("slot-{}.c8ss", slot + 1))
+    }
+}
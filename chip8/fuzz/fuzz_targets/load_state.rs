@@ -0,0 +1,28 @@
+#![no_main]
+
+use chip8::{StepOutcome, VirtualMachine};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_INSTRUCTIONS: usize = 10_000;
+
+// Arbitrary bytes as a save state, then whatever state was accepted is run
+fuzz_target!(|data: &[u8]| {
+    let mut vm = VirtualMachine::new();
+    if vm.load_state(data).is_err() {
+        return;
+    }
+
+    for step in 0..MAX_INSTRUCTIONS {
+        match vm.execute_instruction() {
+            Ok(StepOutcome::Executed) => {}
+            Ok(StepOutcome::BlockedOnKeyPress) => vm.complete_fx0a(step as u8 & 0xF),
+            Ok(StepOutcome::WaitingForVblank) => vm.decrement_timers(),
+            Ok(StepOutcome::Exited) | Err(_) => break,
+        }
+    }
+
+    // Everything a frontend or debugger reads after a frame
+    let _ = (vm.stack(), vm.memory(), vm.timers(), vm.pc(), vm.index());
+    let _ = vm.screen_rows().count();
+    let _ = vm.save_state();
+});
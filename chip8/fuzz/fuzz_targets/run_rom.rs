@@ -0,0 +1,88 @@
+#![no_main]
+
+use chip8::{
+    Font, IndexIncrement, InstructionSet, Quirks, SeededRandom, StepOutcome, Timers, VirtualMachine,
+};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_INSTRUCTIONS: usize = 10_000;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    instruction_set: u8,
+    quirks: [bool; 5],
+    load_store_index: u8,
+    seed: u64,
+    stack_depth: u8,
+    vip_font: bool,
+    registers: [u8; 16],
+    stack: Vec<u16>,
+    index: u16,
+    pc: Option<u16>,
+    timers: (u8, u8),
+    keys: u16,
+    rom: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let instruction_set = match input.instruction_set % 3 {
+        0 => InstructionSet::Chip8,
+        1 => InstructionSet::SuperChip,
+        _ => InstructionSet::XoChip,
+    };
+    let [shift_uses_vy, jump_uses_vx, vf_reset, clip_sprites, display_wait] = input.quirks;
+    let quirks = Quirks {
+        shift_uses_vy,
+        load_store_index: match input.load_store_index % 3 {
+            0 => IndexIncrement::None,
+            1 => IndexIncrement::X,
+            _ => IndexIncrement::XPlusOne,
+        },
+        jump_uses_vx,
+        vf_reset,
+        clip_sprites,
+        display_wait,
+    };
+
+    let mut vm =
+        VirtualMachine::with_random_source(instruction_set, quirks, SeededRandom::new(input.seed));
+    vm.set_stack_depth(input.stack_depth % 16 + 1);
+    vm.set_font(if input.vip_font {
+        Font::CosmacVip
+    } else {
+        Font::Chip48
+    });
+    if vm.load_rom(&input.rom).is_err() {
+        return;
+    }
+    for (register, &value) in input.registers.iter().enumerate() {
+        vm.set_register(register as u8, value);
+    }
+    vm.set_stack(&input.stack[..input.stack.len().min(16)]);
+    vm.set_index(input.index);
+    if let Some(pc) = input.pc {
+        vm.set_pc(pc);
+    }
+    vm.set_timers(Timers {
+        delay: input.timers.0,
+        sound: input.timers.1,
+    });
+    for key in 0..16 {
+        vm.key_state[key] = input.keys & 1 << key != 0;
+    }
+
+    for step in 0..MAX_INSTRUCTIONS {
+        match vm.execute_instruction() {
+            Ok(StepOutcome::Executed) => {}
+            Ok(StepOutcome::BlockedOnKeyPress) => vm.complete_fx0a(step as u8 & 0xF),
+            Ok(StepOutcome::WaitingForVblank) => vm.decrement_timers(),
+            Ok(StepOutcome::Exited) | Err(_) => break,
+        }
+    }
+
+    // Everything a frontend or debugger reads after a frame
+    let _ = (vm.stack(), vm.memory(), vm.timers(), vm.pc(), vm.index());
+    let _ = vm.screen_rows().count();
+    let _ = vm.save_state();
+});
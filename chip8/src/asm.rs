@@ -0,0 +1,740 @@
+//! Assembler for Octo source.
+//!
+//! Covers the instructions of all three instruction sets, labels, `:const`,
+//! `:alias`, `:macro`, `:byte`, `:call`, `loop`/`while`/`again` and both
+//! `if ... then` and `if ... begin ... else ... end`. Only `==`, `!=`, `key`
+//! and `-key` can be compared on, the comparisons Octo builds out of VF are
+//! left out. Output starts at 0x200, without the `jump main` Octo inserts.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Instruction, PROGAM_COUNTER_START};
+
+const MAX_ROM_SIZE: usize = 0x10000 - PROGAM_COUNTER_START as usize;
+// Stops macros that expand to themselves
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+/// Lines and columns count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler {
+        tokens: tokenize(source),
+        position: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+    };
+
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+
+    assembler.finish()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let start = line.len() - rest.len();
+            tokens.push(Token {
+                text: &rest[..len],
+                line: line_index + 1,
+                column: line[..start].chars().count() + 1,
+            });
+            rest = &rest[len..];
+        }
+    }
+
+    tokens
+}
+
+struct Macro<'a> {
+    parameters: Vec<&'a str>,
+    body: Vec<Token<'a>>,
+}
+
+// Address fields filled in once every label is known
+struct Fixup<'a> {
+    label: Token<'a>,
+    offset: usize,
+    long: bool,
+}
+
+enum Block<'a> {
+    Loop {
+        token: Token<'a>,
+        start: u16,
+        /// Jumps out of the loop taken by `while`
+        exits: Vec<(Token<'a>, usize)>,
+    },
+    If {
+        token: Token<'a>,
+        jump: usize,
+    },
+    Else {
+        token: Token<'a>,
+        jump: usize,
+    },
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    constants: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    fixups: Vec<Fixup<'a>>,
+    blocks: Vec<Block<'a>>,
+    expansions: usize,
+}
+
+impl<'a> Assembler<'a> {
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        use Instruction::*;
+
+        let token = self.next()?;
+        let instruction = match token.text {
+            ":" => {
+                let name = self.name()?;
+                return self.define_label(name);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.value(value)?;
+                self.check_undefined(name)?;
+                self.constants.insert(name.text, value);
+                return Ok(());
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+                return Ok(());
+            }
+            ":macro" => return self.define_macro(),
+            ":byte" => {
+                let value = self.next()?;
+                let byte = self.byte(value)?;
+                return self.emit(token, &[byte]);
+            }
+            ":call" => Call(self.address(false)?),
+            "clear" => Cls,
+            "return" | ";" => Ret,
+            "scroll-down" => ScrollDown(self.nibble()?),
+            "scroll-up" => ScrollUp(self.nibble()?),
+            "scroll-right" => ScrollRight,
+            "scroll-left" => ScrollLeft,
+            "exit" => Exit,
+            "lores" => LowRes,
+            "hires" => HighRes,
+            "jump" => Jp(self.address(false)?),
+            "jump0" => JpV0(self.address(false)?),
+            "sprite" => Drw {
+                x: self.register()?,
+                y: self.register()?,
+                n: self.nibble()?,
+            },
+            "bcd" => LdB {
+                x: self.register()?,
+            },
+            "saveflags" => LdRVx {
+                x: self.register()?,
+            },
+            "loadflags" => LdVxR {
+                x: self.register()?,
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if save {
+                        SaveRange { x, y }
+                    } else {
+                        LoadRange { x, y }
+                    }
+                } else if save {
+                    LdIVx { x }
+                } else {
+                    LdVxI { x }
+                }
+            }
+            "plane" => Plane(self.nibble()?),
+            "audio" => Audio,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                match token.text {
+                    "delay" => LdDtVx { x },
+                    "buzzer" => LdStVx { x },
+                    _ => Pitch { x },
+                }
+            }
+            "i" => return self.index_statement(token),
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    token,
+                    start: self.here(token)?,
+                    exits: Vec::new(),
+                });
+                return Ok(());
+            }
+            "while" => {
+                let skip = self.condition()?;
+                let jump = self.rom.len();
+                self.emit_instruction(token, skip)?;
+                self.emit_instruction(token, Jp(0))?;
+                let Some(Block::Loop { exits, .. }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                else {
+                    return Err(token.error("`while` outside of a loop"));
+                };
+                exits.push((token, jump + 2));
+                return Ok(());
+            }
+            "again" => {
+                let Some(Block::Loop { start, exits, .. }) = self.blocks.pop() else {
+                    return Err(token.error("`again` without a matching `loop`"));
+                };
+                self.emit_instruction(token, Jp(start))?;
+                for (token, jump) in exits {
+                    self.patch_jump(token, jump)?;
+                }
+                return Ok(());
+            }
+            "if" => {
+                let skip = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text {
+                    "then" => negate(skip),
+                    "begin" => {
+                        self.emit_instruction(token, skip)?;
+                        self.blocks.push(Block::If {
+                            token,
+                            jump: self.rom.len(),
+                        });
+                        Jp(0)
+                    }
+                    _ => return Err(keyword.error("expected `then` or `begin`")),
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump, .. }) = self.blocks.pop() else {
+                    return Err(token.error("`else` without a matching `if ... begin`"));
+                };
+                self.blocks.push(Block::Else {
+                    token,
+                    jump: self.rom.len(),
+                });
+                self.emit_instruction(token, Jp(0))?;
+                return self.patch_jump(token, jump);
+            }
+            "end" => {
+                let Some(Block::If { jump, .. } | Block::Else { jump, .. }) = self.blocks.pop()
+                else {
+                    return Err(token.error("`end` without a matching `if ... begin`"));
+                };
+                return self.patch_jump(token, jump);
+            }
+            name if self.macros.contains_key(name) => return self.expand_macro(token),
+            _ => {
+                if let Some(x) = self.register_value(token) {
+                    self.register_statement(x)?
+                } else if let Some(value) = parse_number(token.text) {
+                    let byte = byte_value(token, value)?;
+                    return self.emit(token, &[byte]);
+                } else if is_name(token.text) {
+                    // A bare label calls it
+                    Call(self.label_address(token, false)?)
+                } else {
+                    return Err(token.error(format!("unexpected `{}`", token.text)));
+                }
+            }
+        };
+
+        self.emit_instruction(token, instruction)
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<Instruction, AssembleError> {
+        use Instruction::*;
+
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let y = self.register_value(operand);
+
+        let instruction = match (operator.text, y) {
+            (":=", _) if operand.text == "random" => Rnd {
+                x,
+                byte: {
+                    let mask = self.next()?;
+                    self.byte(mask)?
+                },
+            },
+            (":=", _) if operand.text == "delay" => LdVxDt { x },
+            (":=", _) if operand.text == "key" => LdVxK { x },
+            (":=", Some(y)) => LdReg { x, y },
+            (":=", None) => LdByte {
+                x,
+                byte: self.byte(operand)?,
+            },
+            ("+=", Some(y)) => AddReg { x, y },
+            ("+=", None) => AddByte {
+                x,
+                byte: self.byte(operand)?,
+            },
+            ("-=", Some(y)) => Sub { x, y },
+            ("-=", None) => AddByte {
+                x,
+                byte: self.byte(operand)?.wrapping_neg(),
+            },
+            ("=-", Some(y)) => Subn { x, y },
+            ("|=", Some(y)) => Or { x, y },
+            ("&=", Some(y)) => And { x, y },
+            ("^=", Some(y)) => Xor { x, y },
+            (">>=", Some(y)) => Shr { x, y },
+            ("<<=", Some(y)) => Shl { x, y },
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(operand.error("expected a register"))
+            }
+            _ => {
+                return Err(operator.error(format!(
+                    "unknown operator `{}` for a register",
+                    operator.text
+                )))
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    fn index_statement(&mut self, token: Token<'a>) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        let instruction = match operator.text {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.next()?;
+                    let address = self.address(true)?;
+                    self.emit_instruction(token, Instruction::LdILong)?;
+                    return self.emit(token, &address.to_be_bytes());
+                }
+                Some("hex") => {
+                    self.next()?;
+                    Instruction::LdF {
+                        x: self.register()?,
+                    }
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    Instruction::LdHf {
+                        x: self.register()?,
+                    }
+                }
+                _ => Instruction::LdI(self.address(false)?),
+            },
+            "+=" => Instruction::AddI {
+                x: self.register()?,
+            },
+            _ => return Err(operator.error("expected `:=` or `+=` after `i`")),
+        };
+
+        self.emit_instruction(token, instruction)
+    }
+
+    // The instruction that skips when the condition holds
+    fn condition(&mut self) -> Result<Instruction, AssembleError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        let skip = match operator.text {
+            "key" => Instruction::Skp { x },
+            "-key" => Instruction::Sknp { x },
+            "==" | "!=" => {
+                let operand = self.next()?;
+                let equal = match self.register_value(operand) {
+                    Some(y) => Instruction::SeReg { x, y },
+                    None => Instruction::SeByte {
+                        x,
+                        byte: self.byte(operand)?,
+                    },
+                };
+                if operator.text == "==" {
+                    equal
+                } else {
+                    negate(equal)
+                }
+            }
+            _ => {
+                return Err(operator.error(format!(
+                    "unsupported comparison `{}`, expected ==, !=, key or -key",
+                    operator.text
+                )))
+            }
+        };
+
+        Ok(skip)
+    }
+
+    fn define_label(&mut self, name: Token<'a>) -> Result<(), AssembleError> {
+        self.check_undefined(name)?;
+        let here = self.here(name)?;
+        self.labels.insert(name.text, here);
+        Ok(())
+    }
+
+    fn check_undefined(&self, name: Token<'a>) -> Result<(), AssembleError> {
+        if self.labels.contains_key(name.text) || self.constants.contains_key(name.text) {
+            return Err(name.error(format!("`{}` is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.name()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            parameters.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let Some(&token) = self.tokens.get(self.position) else {
+                return Err(name.error(format!("macro `{}` is missing its `}}`", name.text)));
+            };
+            self.position += 1;
+            match token.text {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, call: Token<'a>) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(call.error("too many macro expansions, does a macro use itself?"));
+        }
+
+        let parameters = self.macros[call.text].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..parameters {
+            let argument = self.next()?;
+            arguments.insert(self.macros[call.text].parameters[index], argument.text);
+        }
+
+        let expansion: Vec<Token> = self.macros[call.text]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: arguments.get(token.text).copied().unwrap_or(token.text),
+                ..*token
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, expansion);
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AssembleError> {
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::Loop { token, .. } => token.error("`loop` is never closed by `again`"),
+                Block::If { token, .. } | Block::Else { token, .. } => {
+                    token.error("`if ... begin` is never closed by `end`")
+                }
+            });
+        }
+
+        for fixup in &self.fixups {
+            let Some(&address) = self.labels.get(fixup.label.text) else {
+                return Err(fixup
+                    .label
+                    .error(format!("undefined label `{}`", fixup.label.text)));
+            };
+            patch_address(
+                &mut self.rom,
+                fixup.label,
+                fixup.offset,
+                address,
+                fixup.long,
+            )?;
+        }
+
+        Ok(self.rom)
+    }
+
+    // A ROM filling memory has no address after it
+    fn here(&self, token: Token<'a>) -> Result<u16, AssembleError> {
+        u16::try_from(PROGAM_COUNTER_START as usize + self.rom.len())
+            .map_err(|_| token.error("program does not fit in memory"))
+    }
+
+    fn emit(&mut self, token: Token<'a>, bytes: &[u8]) -> Result<(), AssembleError> {
+        if self.rom.len() + bytes.len() > MAX_ROM_SIZE {
+            return Err(token.error("program does not fit in memory"));
+        }
+        self.rom.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn emit_instruction(
+        &mut self,
+        token: Token<'a>,
+        instruction: Instruction,
+    ) -> Result<(), AssembleError> {
+        self.emit(token, &instruction.encode().to_be_bytes())
+    }
+
+    // Points a placeholder jump at the current address
+    fn patch_jump(&mut self, token: Token<'a>, offset: usize) -> Result<(), AssembleError> {
+        let here = self.here(token)?;
+        patch_address(&mut self.rom, token, offset, here, false)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, AssembleError> {
+        let Some(&token) = self.tokens.get(self.position) else {
+            let (line, column) = self.tokens.last().map_or((1, 1), |token| {
+                (token.line, token.column + token.text.chars().count())
+            });
+            return Err(AssembleError {
+                line,
+                column,
+                message: "unexpected end of input".to_string(),
+            });
+        };
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected `{}`", text)));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<Token<'a>, AssembleError> {
+        let token = self.next()?;
+        if !is_name(token.text) || parse_register(token.text).is_some() {
+            return Err(token.error(format!("`{}` can not be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn register_value(&self, token: Token<'a>) -> Option<u8> {
+        self.aliases
+            .get(token.text)
+            .copied()
+            .or_else(|| parse_register(token.text))
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register_value(token)
+            .ok_or_else(|| token.error(format!("expected a register, found `{}`", token.text)))
+    }
+
+    fn known_value(&self, token: Token<'a>) -> Option<i32> {
+        parse_number(token.text)
+            .or_else(|| self.constants.get(token.text).copied())
+            .or_else(|| self.labels.get(token.text).map(|&address| address as i32))
+    }
+
+    fn value(&self, token: Token<'a>) -> Result<i32, AssembleError> {
+        self.known_value(token)
+            .ok_or_else(|| token.error(format!("expected a number, found `{}`", token.text)))
+    }
+
+    fn byte(&self, token: Token<'a>) -> Result<u8, AssembleError> {
+        byte_value(token, self.value(token)?)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.value(token)? {
+            value @ 0..=15 => Ok(value as u8),
+            value => Err(token.error(format!("{} does not fit in 4 bits", value))),
+        }
+    }
+
+    fn address(&mut self, long: bool) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        self.label_address(token, long)
+    }
+
+    // Labels that are not defined yet are filled in by `finish`
+    fn label_address(&mut self, token: Token<'a>, long: bool) -> Result<u16, AssembleError> {
+        if let Some(value) = self.known_value(token) {
+            return address_value(token, value, long);
+        }
+        if !is_name(token.text) {
+            return Err(token.error(format!("expected an address, found `{}`", token.text)));
+        }
+
+        self.fixups.push(Fixup {
+            label: token,
+            offset: self.rom.len() + if long { 2 } else { 0 },
+            long,
+        });
+        Ok(0)
+    }
+}
+
+fn negate(skip: Instruction) -> Instruction {
+    use Instruction::*;
+
+    match skip {
+        SeByte { x, byte } => SneByte { x, byte },
+        SneByte { x, byte } => SeByte { x, byte },
+        SeReg { x, y } => SneReg { x, y },
+        SneReg { x, y } => SeReg { x, y },
+        Skp { x } => Sknp { x },
+        Sknp { x } => Skp { x },
+        instruction => instruction,
+    }
+}
+
+fn patch_address(
+    rom: &mut [u8],
+    token: Token,
+    offset: usize,
+    address: u16,
+    long: bool,
+) -> Result<(), AssembleError> {
+    let address = address_value(token, address as i32, long)?;
+    let [high, low] = address.to_be_bytes();
+    if long {
+        rom[offset] = high;
+    } else {
+        rom[offset] = rom[offset] & 0xF0 | high;
+    }
+    rom[offset + 1] = low;
+    Ok(())
+}
+
+fn address_value(token: Token, value: i32, long: bool) -> Result<u16, AssembleError> {
+    let max = if long { 0xFFFF } else { 0xFFF };
+    if !(0..=max).contains(&value) {
+        return Err(token.error(format!(
+            "address {:#X} does not fit in {} bits",
+            value,
+            if long { 16 } else { 12 }
+        )));
+    }
+    Ok(value as u16)
+}
+
+// Negative bytes are stored as two's complement
+fn byte_value(token: Token, value: i32) -> Result<u8, AssembleError> {
+    if !(-128..=255).contains(&value) {
+        return Err(token.error(format!("{} does not fit in a byte", value)));
+    }
+    Ok(value as u8)
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x") {
+        (16, hex)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        (2, binary)
+    } else {
+        (10, digits)
+    };
+    // from_str_radix would accept a second sign
+    if !digits.starts_with(|c: char| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+
+    if negative {
+        value.checked_neg()
+    } else {
+        Some(value)
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(first) if first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
@@ -0,0 +1,307 @@
+//! ROM metadata keyed by SHA-1, read from the `programs.json` of the
+//! community CHIP-8 database (<https://github.com/chip-8/chip-8-database>).
+//!
+//! `Database::builtin` is built from `data/programs.json`, which uses the same
+//! format, so the upstream file can replace it as is.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::json::{self, Value};
+pub use crate::sha1::sha1;
+use crate::{
+    IndexIncrement, InstructionSet, LoadError, LoadOptions, Platform, Quirks, RandomSource,
+    VirtualMachine, PROGAM_COUNTER_START,
+};
+
+const BUILTIN: &str = include_str!("../data/programs.json");
+
+/// What the database knows about one ROM, resolved to settings of this
+/// interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    /// Database id of the platform the ROM runs on, such as `superchip`
+    pub platform: String,
+    pub instruction_set: InstructionSet,
+    pub quirks: Quirks,
+    /// Instructions per frame
+    pub tick_rate: Option<u32>,
+    pub start_address: u16,
+    /// Buttons such as `up` or `a` and the key each one stands for
+    pub keys: Vec<(String, u8)>,
+    /// RGB colors indexed by which bitplanes a pixel is lit on
+    pub colors: Vec<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseError {
+    /// Not valid JSON, with the byte offset of the problem
+    Syntax(usize),
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Syntax(offset) => write!(f, "Invalid JSON at byte {}", offset),
+            DatabaseError::InvalidValue(field) => write!(f, "Database has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    roms: HashMap<[u8; 20], RomInfo>,
+}
+
+impl Database {
+    pub fn builtin() -> Database {
+        Database::from_json(BUILTIN).expect("the built in database is valid")
+    }
+
+    /// Reads the database's `programs.json`. ROMs that only run on platforms
+    /// this interpreter lacks, such as MEGA-CHIP, are left out.
+    pub fn from_json(programs: &str) -> Result<Database, DatabaseError> {
+        let programs = json::parse(programs).map_err(DatabaseError::Syntax)?;
+        let programs = programs
+            .as_array()
+            .ok_or(DatabaseError::InvalidValue("program list"))?;
+
+        let mut roms = HashMap::new();
+        for program in programs {
+            let title = optional_str(program, "title")?.unwrap_or_default();
+            let authors = match program.get("authors") {
+                Some(authors) => authors
+                    .as_array()
+                    .and_then(|authors| {
+                        authors
+                            .iter()
+                            .map(|author| author.as_str().map(str::to_owned))
+                            .collect()
+                    })
+                    .ok_or(DatabaseError::InvalidValue("author list"))?,
+                None => Vec::new(),
+            };
+
+            let Some(program_roms) = program.get("roms") else {
+                continue;
+            };
+            let program_roms = program_roms
+                .as_object()
+                .ok_or(DatabaseError::InvalidValue("ROM list"))?;
+            for (hash, rom) in program_roms {
+                let hash = parse_sha1(hash).ok_or(DatabaseError::InvalidValue("SHA-1"))?;
+                if let Some(info) = rom_info(rom, &title, &authors)? {
+                    roms.insert(hash, info);
+                }
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn get(&self, sha1: &[u8; 20]) -> Option<&RomInfo> {
+        self.roms.get(sha1)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&sha1(rom))
+    }
+}
+
+impl<R: RandomSource> VirtualMachine<R> {
+    /// Loads `rom` like `load_rom`, but first switches to the instruction set,
+    /// quirks and start address `database` has for it. Returns the entry the
+    /// ROM was recognized as, if any. On error the VM is left untouched.
+    pub fn load_rom_with_database<'a>(
+        &mut self,
+        rom: &[u8],
+        database: &'a Database,
+    ) -> Result<Option<&'a RomInfo>, LoadError> {
+        let Some(info) = database.lookup(rom) else {
+            return self.load_rom(rom).map(|()| None);
+        };
+
+        let previous = (self.instruction_set, self.quirks);
+        self.instruction_set = info.instruction_set;
+        self.quirks = info.quirks;
+        let options = LoadOptions {
+            load_address: info.start_address,
+            entry_point: info.start_address,
+        };
+        if let Err(error) = self.load_rom_with(rom, options) {
+            (self.instruction_set, self.quirks) = previous;
+            return Err(error);
+        }
+        Ok(Some(info))
+    }
+}
+
+/// The instruction set and quirks the database's platform ids stand for
+fn platform_config(platform: &str) -> Option<(InstructionSet, Quirks)> {
+    let preset = |platform: Platform| Some((platform.instruction_set(), platform.quirks()));
+    match platform {
+        "originalChip8" | "hybridVIP" => preset(Platform::CosmacVip),
+        "modernChip8" => Some((InstructionSet::Chip8, Quirks::default())),
+        "chip48" => preset(Platform::Chip48),
+        // SUPER-CHIP 1.0 still advanced I like CHIP-48, 1.1 stopped
+        "superchip1" => Some((InstructionSet::SuperChip, Quirks::CHIP_48)),
+        "superchip" => preset(Platform::SuperChipModern),
+        "xochip" => preset(Platform::XoChip),
+        _ => None,
+    }
+}
+
+fn rom_info(
+    rom: &Value,
+    title: &str,
+    authors: &[String],
+) -> Result<Option<RomInfo>, DatabaseError> {
+    let platforms = rom
+        .get("platforms")
+        .and_then(Value::as_array)
+        .ok_or(DatabaseError::InvalidValue("platform list"))?;
+    let supported = platforms.iter().find_map(|platform| {
+        let platform = platform.as_str()?;
+        platform_config(platform).map(|config| (platform, config))
+    });
+    let Some((platform, (instruction_set, mut quirks))) = supported else {
+        return Ok(None);
+    };
+
+    if let Some(overrides) = rom
+        .get("quirkyPlatforms")
+        .and_then(|quirky| quirky.get(platform))
+    {
+        apply_quirks(&mut quirks, overrides)?;
+    }
+
+    let tick_rate = match rom.get("tickrate") {
+        Some(tick_rate) => {
+            Some(number(tick_rate, u32::MAX).ok_or(DatabaseError::InvalidValue("tick rate"))?)
+        }
+        None => None,
+    };
+    let start_address = match rom.get("startAddress") {
+        Some(address) => {
+            number(address, 0xFFFF).ok_or(DatabaseError::InvalidValue("start address"))? as u16
+        }
+        None => PROGAM_COUNTER_START,
+    };
+
+    let mut keys = Vec::new();
+    if let Some(bindings) = rom.get("keys") {
+        let bindings = bindings
+            .as_object()
+            .ok_or(DatabaseError::InvalidValue("key list"))?;
+        for (button, key) in bindings {
+            let key = number(key, 0xF).ok_or(DatabaseError::InvalidValue("key"))?;
+            keys.push((button.clone(), key as u8));
+        }
+    }
+
+    let mut colors = Vec::new();
+    if let Some(pixels) = rom.get("colors").and_then(|colors| colors.get("pixels")) {
+        let pixels = pixels
+            .as_array()
+            .ok_or(DatabaseError::InvalidValue("color list"))?;
+        for pixel in pixels {
+            colors.push(
+                pixel
+                    .as_str()
+                    .and_then(parse_color)
+                    .ok_or(DatabaseError::InvalidValue("color"))?,
+            );
+        }
+    }
+
+    Ok(Some(RomInfo {
+        title: title.to_owned(),
+        authors: authors.to_vec(),
+        platform: platform.to_owned(),
+        instruction_set,
+        quirks,
+        tick_rate,
+        start_address,
+        keys,
+        colors,
+    }))
+}
+
+/// Applies the database's quirk flags, each `true` where the ROM needs the
+/// behavior that differs from the original COSMAC VIP.
+fn apply_quirks(quirks: &mut Quirks, overrides: &Value) -> Result<(), DatabaseError> {
+    let overrides = overrides
+        .as_object()
+        .ok_or(DatabaseError::InvalidValue("quirk list"))?;
+    let mut leave_index = quirks.load_store_index == IndexIncrement::None;
+    let mut increment_by_x = quirks.load_store_index == IndexIncrement::X;
+    for (name, value) in overrides {
+        let value = value
+            .as_bool()
+            .ok_or(DatabaseError::InvalidValue("quirk"))?;
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !value,
+            "memoryLeaveIUnchanged" => leave_index = value,
+            "memoryIncrementByX" => increment_by_x = value,
+            "wrap" => quirks.clip_sprites = !value,
+            "jump" => quirks.jump_uses_vx = value,
+            "vblank" => quirks.display_wait = value,
+            "logic" => quirks.vf_reset = value,
+            _ => {}
+        }
+    }
+    quirks.load_store_index = if leave_index {
+        IndexIncrement::None
+    } else if increment_by_x {
+        IndexIncrement::X
+    } else {
+        IndexIncrement::XPlusOne
+    };
+    Ok(())
+}
+
+fn optional_str(value: &Value, key: &'static str) -> Result<Option<String>, DatabaseError> {
+    match value.get(key) {
+        Some(text) => text
+            .as_str()
+            .map(|text| Some(text.to_owned()))
+            .ok_or(DatabaseError::InvalidValue(key)),
+        None => Ok(None),
+    }
+}
+
+/// A whole number from 0 to `max`
+fn number(value: &Value, max: u32) -> Option<u32> {
+    let number = value.as_f64()?;
+    (number.fract() == 0.0 && (0.0..=max as f64).contains(&number)).then_some(number as u32)
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    let mut hash = [0; 20];
+    if text.len() != 40 {
+        return None;
+    }
+    for (byte, digits) in hash.iter_mut().zip(text.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// `#RRGGBB`
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let digits = text.strip_prefix('#').filter(|digits| digits.len() == 6)?;
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
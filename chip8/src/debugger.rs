@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    decode, Hooks, Instruction, RandomSource, SeededRandom, StepOutcome, VirtualMachine, VmError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Why a debugger stopped running. Watchpoints stop after the instruction at
+/// `pc` has run, breakpoints before the one at their address does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A single step, step over or step out finished
+    Stepped,
+    Breakpoint {
+        pc: u16,
+    },
+    MemoryRead {
+        pc: u16,
+        address: u16,
+    },
+    MemoryWrite {
+        pc: u16,
+        address: u16,
+    },
+    RegisterChanged {
+        pc: u16,
+        register: u8,
+        old: u8,
+        new: u8,
+    },
+    /// The instruction budget given to a run ran out
+    InstructionLimit,
+    BlockedOnKeyPress,
+    WaitingForVblank,
+    Exited,
+    Fault(VmError),
+}
+
+/// Runs a VM under breakpoints and watchpoints. Timers and keys are still up
+/// to the host, through `vm_mut`.
+#[derive(Debug)]
+pub struct Debugger<R: RandomSource = SeededRandom> {
+    vm: VirtualMachine<R>,
+    breakpoints: BTreeSet<u16>,
+    read_watchpoints: BTreeSet<u16>,
+    write_watchpoints: BTreeSet<u16>,
+    register_watchpoints: BTreeSet<u8>,
+}
+
+impl<R: RandomSource> Debugger<R> {
+    pub fn new(vm: VirtualMachine<R>) -> Self {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            read_watchpoints: BTreeSet::new(),
+            write_watchpoints: BTreeSet::new(),
+            register_watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VirtualMachine<R> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine<R> {
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> VirtualMachine<R> {
+        self.vm
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, kind: WatchKind) {
+        if kind != WatchKind::Write {
+            self.read_watchpoints.insert(address);
+        }
+        if kind != WatchKind::Read {
+            self.write_watchpoints.insert(address);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.read_watchpoints.remove(&address);
+        self.write_watchpoints.remove(&address);
+    }
+
+    /// Stops whenever an instruction changes V`register`
+    pub fn watch_register(&mut self, register: u8) {
+        self.register_watchpoints.insert(register & 0xF);
+    }
+
+    pub fn unwatch_register(&mut self, register: u8) {
+        self.register_watchpoints.remove(&(register & 0xF));
+    }
+
+    /// Runs one instruction. A breakpoint on it is ignored, so stepping off a
+    /// breakpoint works.
+    pub fn step(&mut self) -> StopReason {
+        self.execute(false).unwrap_or(StopReason::Stepped)
+    }
+
+    /// Steps, but runs a 2NNN call through to its return
+    pub fn step_over(&mut self, max_instructions: usize) -> StopReason {
+        let pc = self.vm.program_counter;
+        let stack_pointer = self.vm.stack_pointer;
+        let is_call = matches!(
+            self.vm.fetch_opcode().map(decode),
+            Ok(Ok(Instruction::Call(_)))
+        );
+        if !is_call {
+            return self.step();
+        }
+
+        self.run_until(max_instructions, |vm| {
+            vm.stack_pointer == stack_pointer && vm.program_counter == pc.wrapping_add(2)
+        })
+    }
+
+    /// Runs until the 00EE that returns from the current subroutine
+    pub fn step_out(&mut self, max_instructions: usize) -> StopReason {
+        let stack_pointer = self.vm.stack_pointer;
+
+        self.run_until(max_instructions, |vm| vm.stack_pointer < stack_pointer)
+    }
+
+    /// Runs until a breakpoint, watchpoint or anything else stops the VM
+    pub fn run(&mut self, max_instructions: usize) -> StopReason {
+        self.run_until(max_instructions, |_| false)
+    }
+
+    fn run_until(
+        &mut self,
+        max_instructions: usize,
+        done: impl Fn(&VirtualMachine<R>) -> bool,
+    ) -> StopReason {
+        for executed in 0..max_instructions {
+            if let Some(reason) = self.execute(executed > 0) {
+                return reason;
+            }
+            if done(&self.vm) {
+                return StopReason::Stepped;
+            }
+        }
+
+        StopReason::InstructionLimit
+    }
+
+    fn execute(&mut self, check_breakpoint: bool) -> Option<StopReason> {
+        let pc = self.vm.program_counter;
+        if check_breakpoint && self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint { pc });
+        }
+
+        let mut watcher = Watcher {
+            read_watchpoints: &self.read_watchpoints,
+            write_watchpoints: &self.write_watchpoints,
+            read: None,
+            write: None,
+        };
+        let registers = self.vm.registers;
+
+        match self.vm.execute_instruction_with(&mut watcher) {
+            Ok(StepOutcome::Executed) => {}
+            Ok(StepOutcome::BlockedOnKeyPress) => return Some(StopReason::BlockedOnKeyPress),
+            Ok(StepOutcome::WaitingForVblank) => return Some(StopReason::WaitingForVblank),
+            Ok(StepOutcome::Exited) => return Some(StopReason::Exited),
+            Err(error) => return Some(StopReason::Fault(error)),
+        }
+
+        if let Some(address) = watcher.write {
+            return Some(StopReason::MemoryWrite { pc, address });
+        }
+        if let Some(address) = watcher.read {
+            return Some(StopReason::MemoryRead { pc, address });
+        }
+        self.register_watchpoints
+            .iter()
+            .map(|&register| (register, registers[register as usize]))
+            .find(|&(register, old)| self.vm.registers[register as usize] != old)
+            .map(|(register, old)| StopReason::RegisterChanged {
+                pc,
+                register,
+                old,
+                new: self.vm.registers[register as usize],
+            })
+    }
+}
+
+// The lowest watched addresses an instruction read and wrote
+struct Watcher<'a> {
+    read_watchpoints: &'a BTreeSet<u16>,
+    write_watchpoints: &'a BTreeSet<u16>,
+    read: Option<u16>,
+    write: Option<u16>,
+}
+
+impl Hooks for Watcher<'_> {
+    fn on_mem_read(&mut self, address: u16, _value: u8) {
+        if self.read_watchpoints.contains(&address) {
+            self.read = Some(self.read.map_or(address, |read| read.min(address)));
+        }
+    }
+
+    fn on_mem_write(&mut self, address: u16, _value: u8) {
+        if self.write_watchpoints.contains(&address) {
+            self.write = Some(self.write.map_or(address, |write| write.min(address)));
+        }
+    }
+}
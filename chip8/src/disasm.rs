@@ -0,0 +1,354 @@
+//! Turns ROMs back into listings.
+//!
+//! Code is told apart from data by following every path the program can take
+//! from 0x200: jumps, calls, both sides of each skip and the base address of
+//! BNNN tables. Anything never reached is listed as data. Listings are
+//! annotated with the address and raw bytes of every line.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::{decode, Instruction, PROGAM_COUNTER_START};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Cowgod's mnemonics, `LD V0, 0x12`
+    #[default]
+    Classic,
+    /// Octo, `v0 := 0x12`
+    Octo,
+}
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// One flag per ROM byte, set where an instruction reachable from 0x200 starts
+pub fn find_code(rom: &[u8]) -> Vec<bool> {
+    let rom = &rom[..rom.len().min(0x10000 - PROGAM_COUNTER_START as usize)];
+    let mut code = vec![false; rom.len()];
+    let mut pending = vec![PROGAM_COUNTER_START];
+
+    while let Some(address) = pending.pop() {
+        let Some(offset) = rom_offset(rom, address) else {
+            continue;
+        };
+        if code[offset] {
+            continue;
+        }
+        let Some(instruction) = instruction_at(rom, address) else {
+            continue;
+        };
+        code[offset] = true;
+
+        let next = address.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::Jp(target) | Instruction::JpV0(target) => pending.push(target),
+            Instruction::Call(target) => pending.extend([target, next]),
+            instruction if is_skip(instruction) => {
+                let skipped = instruction_at(rom, next).map_or(2, |skipped| skipped.size());
+                pending.extend([next, next.wrapping_add(skipped)]);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    code
+}
+
+/// A listing of `rom` as loaded at 0x200
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let rom = &rom[..rom.len().min(0x10000 - PROGAM_COUNTER_START as usize)];
+    let code = find_code(rom);
+
+    // Work out where every line starts before naming any targets, a label
+    // is only any use if there is a line for it to go on
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGAM_COUNTER_START + offset as u16;
+        let line = match instruction_at(rom, address) {
+            Some(instruction) if code[offset] => Line::Code(instruction),
+            _ => {
+                let len = (offset + 1..rom.len())
+                    .take_while(|&end| end - offset < DATA_BYTES_PER_LINE && !code[end])
+                    .count()
+                    + 1;
+                Line::Data(len)
+            }
+        };
+        lines.push((address, line));
+        offset += line.len(rom, address);
+    }
+
+    let starts: BTreeSet<u16> = lines.iter().map(|&(address, _)| address).collect();
+    let labels: BTreeSet<u16> = lines
+        .iter()
+        .filter_map(|&(address, line)| match line {
+            Line::Code(instruction) => target(rom, address, instruction),
+            Line::Data(_) => None,
+        })
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    // Data lines also end at labels so the label lands on its own byte
+    let mut listing = String::new();
+    for (address, line) in lines {
+        match line {
+            Line::Code(instruction) => {
+                write_label(&mut listing, &labels, address, syntax);
+                let name = |target: u16| {
+                    if syntax == Syntax::Octo && labels.contains(&target) {
+                        label(target)
+                    } else {
+                        format!("0x{:03X}", target)
+                    }
+                };
+                let mut text = render(instruction, syntax, name);
+                if instruction == Instruction::LdILong {
+                    write!(text, " {}", name(long_address(rom, address))).unwrap();
+                }
+                let bytes = bytes_at(rom, address, instruction.size() as usize);
+                write_line(&mut listing, address, bytes, &text, syntax);
+            }
+            Line::Data(len) => {
+                let bytes = bytes_at(rom, address, len);
+                let mut start = 0;
+                for split in 1..=bytes.len() {
+                    if split == bytes.len() || labels.contains(&(address + split as u16)) {
+                        let chunk_address = address + start as u16;
+                        write_label(&mut listing, &labels, chunk_address, syntax);
+                        let chunk = &bytes[start..split];
+                        let text = data(chunk, syntax);
+                        write_line(&mut listing, chunk_address, chunk, &text, syntax);
+                        start = split;
+                    }
+                }
+            }
+        }
+    }
+
+    listing
+}
+
+/// The instruction on its own, with addresses written as numbers. F000 NNNN
+/// reads the address from the word after it, which this leaves off.
+pub fn mnemonic(instruction: Instruction, syntax: Syntax) -> String {
+    render(instruction, syntax, |address| format!("0x{:03X}", address))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Line {
+    Code(Instruction),
+    Data(usize),
+}
+
+impl Line {
+    fn len(&self, rom: &[u8], address: u16) -> usize {
+        match self {
+            Line::Code(instruction) => bytes_at(rom, address, instruction.size() as usize).len(),
+            Line::Data(len) => *len,
+        }
+    }
+}
+
+fn rom_offset(rom: &[u8], address: u16) -> Option<usize> {
+    let offset = address.checked_sub(PROGAM_COUNTER_START)? as usize;
+    (offset < rom.len()).then_some(offset)
+}
+
+fn bytes_at(rom: &[u8], address: u16, len: usize) -> &[u8] {
+    let offset = (address - PROGAM_COUNTER_START) as usize;
+    &rom[offset..(offset + len).min(rom.len())]
+}
+
+fn instruction_at(rom: &[u8], address: u16) -> Option<Instruction> {
+    let offset = rom_offset(rom, address)?;
+    let bytes = rom.get(offset..offset + 2)?;
+    let instruction = decode((bytes[0] as u16) << 8 | bytes[1] as u16).ok()?;
+
+    // The address word of F000 NNNN has to be in the ROM too
+    (offset + instruction.size() as usize <= rom.len()).then_some(instruction)
+}
+
+fn long_address(rom: &[u8], address: u16) -> u16 {
+    let bytes = bytes_at(rom, address, 4);
+    (bytes[2] as u16) << 8 | bytes[3] as u16
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SeByte { .. }
+            | Instruction::SneByte { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. }
+            | Instruction::Skp { .. }
+            | Instruction::Sknp { .. }
+    )
+}
+
+// Address an instruction names, which gets a label if a line starts there
+fn target(rom: &[u8], address: u16, instruction: Instruction) -> Option<u16> {
+    match instruction {
+        Instruction::Jp(target)
+        | Instruction::Call(target)
+        | Instruction::JpV0(target)
+        | Instruction::LdI(target) => Some(target),
+        Instruction::LdILong => Some(long_address(rom, address)),
+        _ => None,
+    }
+}
+
+fn label(address: u16) -> String {
+    format!("label_{:03X}", address)
+}
+
+fn write_label(listing: &mut String, labels: &BTreeSet<u16>, address: u16, syntax: Syntax) {
+    if labels.contains(&address) {
+        match syntax {
+            Syntax::Classic => writeln!(listing, "{}:", label(address)),
+            Syntax::Octo => writeln!(listing, ": {}", label(address)),
+        }
+        .unwrap();
+    }
+}
+
+fn write_line(listing: &mut String, address: u16, bytes: &[u8], text: &str, syntax: Syntax) {
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    match syntax {
+        Syntax::Classic => writeln!(listing, "{:03X}: {:<16} {}", address, hex, text),
+        Syntax::Octo => writeln!(listing, "  {:<30} # {:03X}: {}", text, address, hex),
+    }
+    .unwrap();
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes = bytes.iter().map(|byte| format!("0x{:02X}", byte));
+    match syntax {
+        Syntax::Classic => format!("DB {}", bytes.collect::<Vec<_>>().join(", ")),
+        Syntax::Octo => bytes.collect::<Vec<_>>().join(" "),
+    }
+}
+
+fn render(instruction: Instruction, syntax: Syntax, name: impl Fn(u16) -> String) -> String {
+    match syntax {
+        Syntax::Classic => classic(instruction, name),
+        Syntax::Octo => octo(instruction, name),
+    }
+}
+
+fn classic(instruction: Instruction, name: impl Fn(u16) -> String) -> String {
+    use Instruction::*;
+
+    match instruction {
+        Cls => "CLS".to_string(),
+        Ret => "RET".to_string(),
+        ScrollDown(n) => format!("SCD {}", n),
+        ScrollUp(n) => format!("SCU {}", n),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        LowRes => "LOW".to_string(),
+        HighRes => "HIGH".to_string(),
+        Jp(address) => format!("JP {}", name(address)),
+        Call(address) => format!("CALL {}", name(address)),
+        SeByte { x, byte } => format!("SE V{:X}, 0x{:02X}", x, byte),
+        SneByte { x, byte } => format!("SNE V{:X}, 0x{:02X}", x, byte),
+        SeReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        SaveRange { x, y } => format!("SAVE V{:X}-V{:X}", x, y),
+        LoadRange { x, y } => format!("LOAD V{:X}-V{:X}", x, y),
+        LdByte { x, byte } => format!("LD V{:X}, 0x{:02X}", x, byte),
+        AddByte { x, byte } => format!("ADD V{:X}, 0x{:02X}", x, byte),
+        LdReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        SneReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        LdI(address) => format!("LD I, {}", name(address)),
+        JpV0(address) => format!("JP V0, {}", name(address)),
+        Rnd { x, byte } => format!("RND V{:X}, 0x{:02X}", x, byte),
+        Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Skp { x } => format!("SKP V{:X}", x),
+        Sknp { x } => format!("SKNP V{:X}", x),
+        LdILong => "LD I, LONG".to_string(),
+        Plane(planes) => format!("PLANE {}", planes),
+        Audio => "AUDIO".to_string(),
+        LdVxDt { x } => format!("LD V{:X}, DT", x),
+        LdVxK { x } => format!("LD V{:X}, K", x),
+        LdDtVx { x } => format!("LD DT, V{:X}", x),
+        LdStVx { x } => format!("LD ST, V{:X}", x),
+        AddI { x } => format!("ADD I, V{:X}", x),
+        LdF { x } => format!("LD F, V{:X}", x),
+        LdHf { x } => format!("LD HF, V{:X}", x),
+        LdB { x } => format!("LD B, V{:X}", x),
+        Pitch { x } => format!("PITCH V{:X}", x),
+        LdIVx { x } => format!("LD [I], V{:X}", x),
+        LdVxI { x } => format!("LD V{:X}, [I]", x),
+        LdRVx { x } => format!("LD R, V{:X}", x),
+        LdVxR { x } => format!("LD V{:X}, R", x),
+    }
+}
+
+// Octo's `if ... then` runs the next line when the condition holds, so each
+// skip is written with the opposite of the comparison it skips on
+fn octo(instruction: Instruction, name: impl Fn(u16) -> String) -> String {
+    use Instruction::*;
+
+    match instruction {
+        Cls => "clear".to_string(),
+        Ret => "return".to_string(),
+        ScrollDown(n) => format!("scroll-down {}", n),
+        ScrollUp(n) => format!("scroll-up {}", n),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        LowRes => "lores".to_string(),
+        HighRes => "hires".to_string(),
+        Jp(address) => format!("jump {}", name(address)),
+        Call(address) => format!(":call {}", name(address)),
+        SeByte { x, byte } => format!("if v{:x} != 0x{:02X} then", x, byte),
+        SneByte { x, byte } => format!("if v{:x} == 0x{:02X} then", x, byte),
+        SeReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        LdByte { x, byte } => format!("v{:x} := 0x{:02X}", x, byte),
+        AddByte { x, byte } => format!("v{:x} += 0x{:02X}", x, byte),
+        LdReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Subn { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        SneReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        LdI(address) => format!("i := {}", name(address)),
+        JpV0(address) => format!("jump0 {}", name(address)),
+        Rnd { x, byte } => format!("v{:x} := random 0x{:02X}", x, byte),
+        Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Skp { x } => format!("if v{:x} -key then", x),
+        Sknp { x } => format!("if v{:x} key then", x),
+        LdILong => "i := long".to_string(),
+        Plane(planes) => format!("plane {}", planes),
+        Audio => "audio".to_string(),
+        LdVxDt { x } => format!("v{:x} := delay", x),
+        LdVxK { x } => format!("v{:x} := key", x),
+        LdDtVx { x } => format!("delay := v{:x}", x),
+        LdStVx { x } => format!("buzzer := v{:x}", x),
+        AddI { x } => format!("i += v{:x}", x),
+        LdF { x } => format!("i := hex v{:x}", x),
+        LdHf { x } => format!("i := bighex v{:x}", x),
+        LdB { x } => format!("bcd v{:x}", x),
+        Pitch { x } => format!("pitch := v{:x}", x),
+        LdIVx { x } => format!("save v{:x}", x),
+        LdVxI { x } => format!("load v{:x}", x),
+        LdRVx { x } => format!("saveflags v{:x}", x),
+        LdVxR { x } => format!("loadflags v{:x}", x),
+    }
+}
@@ -0,0 +1,179 @@
+//! Finds where two runs of a program part ways, from traces or from streams
+//! of save states written back to back.
+
+use std::fmt::Write;
+
+use crate::trace::TraceEntry;
+use crate::{state_len, RandomSource, SaveStateError, VirtualMachine};
+
+// Changed memory bytes listed before the rest are only counted
+const MAX_MEMORY_ROWS: usize = 32;
+
+/// Index of the first entry that differs, or where the shorter run ends.
+/// `None` if both runs are the same.
+pub fn first_divergence<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+}
+
+/// Splits a stream of save states written back to back
+pub fn split_states(mut stream: &[u8]) -> Result<Vec<&[u8]>, SaveStateError> {
+    let mut states = Vec::new();
+    while !stream.is_empty() {
+        let len = state_len(stream)?;
+        if len > stream.len() {
+            return Err(SaveStateError::Truncated);
+        }
+        let (state, rest) = stream.split_at(len);
+        states.push(state);
+        stream = rest;
+    }
+
+    Ok(states)
+}
+
+/// Side by side listing of two trace entries, including the stack and the
+/// memory the instruction wrote, rows that differ marked with `<`
+pub fn compare_entries(a: &TraceEntry, b: &TraceEntry) -> String {
+    let mut table = Table::default();
+
+    table.row("PC", format!("{:04X}", a.pc), format!("{:04X}", b.pc));
+    table.row(
+        "opcode",
+        format!("{:04X} {}", a.opcode, a.mnemonic()),
+        format!("{:04X} {}", b.opcode, b.mnemonic()),
+    );
+    for register in 0..16 {
+        table.row(
+            &format!("V{:X}", register),
+            format!("{:02X}", a.registers[register]),
+            format!("{:02X}", b.registers[register]),
+        );
+    }
+    table.row("I", format!("{:04X}", a.index), format!("{:04X}", b.index));
+    table.row(
+        "DT",
+        format!("{:02X}", a.delay_timer),
+        format!("{:02X}", b.delay_timer),
+    );
+    table.row(
+        "ST",
+        format!("{:02X}", a.sound_timer),
+        format!("{:02X}", b.sound_timer),
+    );
+    let stack = |entry: &TraceEntry| {
+        entry
+            .stack
+            .iter()
+            .map(|address| format!("{:04X}", address))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    table.row("stack", stack(a), stack(b));
+    let writes = |entry: &TraceEntry| {
+        entry
+            .writes
+            .iter()
+            .map(|(address, value)| format!("[{:04X}]={:02X}", address, value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    table.row("writes", writes(a), writes(b));
+
+    table.text
+}
+
+/// Side by side listing of two VMs, including the stack and every memory byte
+/// that differs
+pub fn compare_vms<R: RandomSource>(a: &VirtualMachine<R>, b: &VirtualMachine<R>) -> String {
+    let mut table = Table::default();
+
+    table.row("PC", format!("{:04X}", a.pc()), format!("{:04X}", b.pc()));
+    for register in 0..16 {
+        table.row(
+            &format!("V{:X}", register),
+            format!("{:02X}", a.registers()[register]),
+            format!("{:02X}", b.registers()[register]),
+        );
+    }
+    table.row(
+        "I",
+        format!("{:04X}", a.index()),
+        format!("{:04X}", b.index()),
+    );
+    let (timers_a, timers_b) = (a.timers(), b.timers());
+    table.row(
+        "DT",
+        format!("{:02X}", timers_a.delay),
+        format!("{:02X}", timers_b.delay),
+    );
+    table.row(
+        "ST",
+        format!("{:02X}", timers_a.sound),
+        format!("{:02X}", timers_b.sound),
+    );
+    let stack = |vm: &VirtualMachine<R>| {
+        vm.stack()
+            .iter()
+            .map(|address| format!("{:04X}", address))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    table.row("stack", stack(a), stack(b));
+    table.row(
+        "mode",
+        format!("{:?}", a.instruction_set()),
+        format!("{:?}", b.instruction_set()),
+    );
+
+    let (memory_a, memory_b) = (a.memory(), b.memory());
+    let changed: Vec<usize> = (0..memory_a.len().max(memory_b.len()))
+        .filter(|&address| memory_a.get(address) != memory_b.get(address))
+        .collect();
+    let byte = |memory: &[u8], address: usize| {
+        memory
+            .get(address)
+            .map_or("--".to_string(), |byte| format!("{:02X}", byte))
+    };
+    for &address in changed.iter().take(MAX_MEMORY_ROWS) {
+        table.row(
+            &format!("[{:04X}]", address),
+            byte(memory_a, address),
+            byte(memory_b, address),
+        );
+    }
+    if changed.len() > MAX_MEMORY_ROWS {
+        writeln!(
+            table.text,
+            "... {} more memory bytes differ",
+            changed.len() - MAX_MEMORY_ROWS
+        )
+        .unwrap();
+    }
+
+    let pixels: usize = a
+        .screen_rows()
+        .zip(b.screen_rows())
+        .map(|(a, b)| a.iter().zip(&b).filter(|(a, b)| a != b).count())
+        .sum();
+    if pixels > 0 || a.high_resolution != b.high_resolution {
+        writeln!(table.text, "{} pixels differ", pixels).unwrap();
+    }
+
+    table.text
+}
+
+#[derive(Default)]
+struct Table {
+    text: String,
+}
+
+impl Table {
+    fn row(&mut self, name: &str, a: String, b: String) {
+        let row = format!("{:<8} {:<24} {:<24}", name, a, b);
+        let marker = if a != b { " <" } else { "" };
+        writeln!(self.text, "{}{}", row.trim_end(), marker).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt;
+
+/// A fault raised by the instruction at `pc`. Apart from `PcOutOfBounds`, the
+/// VM is left as the faulting instruction found it, so a host can report the
+/// error and carry on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    StackOverflow {
+        pc: u16,
+        opcode: u16,
+    },
+    StackUnderflow {
+        pc: u16,
+        opcode: u16,
+    },
+    MemoryOutOfBounds {
+        pc: u16,
+        opcode: u16,
+        address: usize,
+    },
+    /// Raised when the program counter no longer points at a whole opcode,
+    /// either after `opcode` has moved it there or when fetching from it. In
+    /// the second case `opcode` holds whatever part of it could be read.
+    PcOutOfBounds {
+        pc: u16,
+        opcode: u16,
+    },
+}
+
+impl VmError {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            VmError::UnknownOpcode { pc, .. }
+            | VmError::StackOverflow { pc, .. }
+            | VmError::StackUnderflow { pc, .. }
+            | VmError::MemoryOutOfBounds { pc, .. }
+            | VmError::PcOutOfBounds { pc, .. } => pc,
+        }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            VmError::UnknownOpcode { opcode, .. }
+            | VmError::StackOverflow { opcode, .. }
+            | VmError::StackUnderflow { opcode, .. }
+            | VmError::MemoryOutOfBounds { opcode, .. }
+            | VmError::PcOutOfBounds { opcode, .. } => opcode,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            VmError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:03X}", opcode, pc)
+            }
+            VmError::StackOverflow { pc, opcode } => {
+                write!(f, "Stack overflow by {:04X} at {:03X}", opcode, pc)
+            }
+            VmError::StackUnderflow { pc, opcode } => {
+                write!(f, "Stack underflow by {:04X} at {:03X}", opcode, pc)
+            }
+            VmError::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "Memory access to {:X} by {:04X} at {:03X} is out of bounds",
+                address, opcode, pc
+            ),
+            VmError::PcOutOfBounds { pc, opcode } => write!(
+                f,
+                "Program counter left memory after {:04X} at {:03X}",
+                opcode, pc
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VmError {}
@@ -0,0 +1,26 @@
+/// Callbacks a host can pass to `execute_instruction_with` and
+/// `decrement_timers_with` to follow what a program does. Every method does
+/// nothing by default, and `()` implements the trait with no callbacks at all,
+/// which is what `execute_instruction` and `decrement_timers` run with.
+pub trait Hooks {
+    /// An opcode was fetched and is about to be decoded and run
+    fn on_instruction(&mut self, _pc: u16, _opcode: u16) {}
+
+    /// An instruction read a byte of data. Instruction fetches, including the
+    /// address after F000, are not reported.
+    fn on_mem_read(&mut self, _address: u16, _value: u8) {}
+
+    /// An instruction wrote `value` to memory
+    fn on_mem_write(&mut self, _address: u16, _value: u8) {}
+
+    /// DXYN drew an `n` row sprite at (`x`, `y`), the values of VX and VY
+    fn on_draw(&mut self, _x: u8, _y: u8, _n: u8, _collided: bool) {}
+
+    /// The sound timer was set while it was stopped
+    fn on_sound_start(&mut self) {}
+
+    /// The sound timer ran out or was set to 0
+    fn on_sound_stop(&mut self) {}
+}
+
+impl Hooks for () {}
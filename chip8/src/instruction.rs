@@ -0,0 +1,292 @@
+use core::fmt;
+
+use crate::InstructionSet;
+
+/// A decoded opcode. Register operands are indices into V0 to VF, names follow
+/// Cowgod's reference where it has one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    LowRes,
+    /// 00FF
+    HighRes,
+    /// 1NNN
+    Jp(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SeByte { x: u8, byte: u8 },
+    /// 4XNN
+    SneByte { x: u8, byte: u8 },
+    /// 5XY0
+    SeReg { x: u8, y: u8 },
+    /// 5XY2
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    LdByte { x: u8, byte: u8 },
+    /// 7XNN
+    AddByte { x: u8, byte: u8 },
+    /// 8XY0
+    LdReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    Shr { x: u8, y: u8 },
+    /// 8XY7
+    Subn { x: u8, y: u8 },
+    /// 8XYE
+    Shl { x: u8, y: u8 },
+    /// 9XY0
+    SneReg { x: u8, y: u8 },
+    /// ANNN
+    LdI(u16),
+    /// BNNN, or BXNN with the jump quirk, where X is the top nibble of the address
+    JpV0(u16),
+    /// CXNN
+    Rnd { x: u8, byte: u8 },
+    /// DXYN, a 16x16 sprite when `n` is 0 on SUPER-CHIP and XO-CHIP
+    Drw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    Skp { x: u8 },
+    /// EXA1
+    Sknp { x: u8 },
+    /// F000 NNNN, the address is the word after the opcode
+    LdILong,
+    /// FN01
+    Plane(u8),
+    /// F002
+    Audio,
+    /// FX07
+    LdVxDt { x: u8 },
+    /// FX0A
+    LdVxK { x: u8 },
+    /// FX15
+    LdDtVx { x: u8 },
+    /// FX18
+    LdStVx { x: u8 },
+    /// FX1E
+    AddI { x: u8 },
+    /// FX29
+    LdF { x: u8 },
+    /// FX30
+    LdHf { x: u8 },
+    /// FX33
+    LdB { x: u8 },
+    /// FX3A
+    Pitch { x: u8 },
+    /// FX55
+    LdIVx { x: u8 },
+    /// FX65
+    LdVxI { x: u8 },
+    /// FX75
+    LdRVx { x: u8 },
+    /// FX85
+    LdVxR { x: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown opcode {:04X}", self.opcode)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Decodes any opcode of the instruction sets this crate knows, whichever one
+/// a VM runs. See `Instruction::instruction_set` for the set that introduced it.
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    use Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let byte = (opcode & 0x00FF) as u8;
+    let address = opcode & 0x0FFF;
+
+    let instruction = match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => Cls,
+        (0x0, 0x0, 0xE, 0xE) => Ret,
+        (0x0, 0x0, 0xC, n) => ScrollDown(n),
+        (0x0, 0x0, 0xD, n) => ScrollUp(n),
+        (0x0, 0x0, 0xF, 0xB) => ScrollRight,
+        (0x0, 0x0, 0xF, 0xC) => ScrollLeft,
+        (0x0, 0x0, 0xF, 0xD) => Exit,
+        (0x0, 0x0, 0xF, 0xE) => LowRes,
+        (0x0, 0x0, 0xF, 0xF) => HighRes,
+        (0x1, ..) => Jp(address),
+        (0x2, ..) => Call(address),
+        (0x3, ..) => SeByte { x, byte },
+        (0x4, ..) => SneByte { x, byte },
+        (0x5, _, _, 0x0) => SeReg { x, y },
+        (0x5, _, _, 0x2) => SaveRange { x, y },
+        (0x5, _, _, 0x3) => LoadRange { x, y },
+        (0x6, ..) => LdByte { x, byte },
+        (0x7, ..) => AddByte { x, byte },
+        (0x8, _, _, 0x0) => LdReg { x, y },
+        (0x8, _, _, 0x1) => Or { x, y },
+        (0x8, _, _, 0x2) => And { x, y },
+        (0x8, _, _, 0x3) => Xor { x, y },
+        (0x8, _, _, 0x4) => AddReg { x, y },
+        (0x8, _, _, 0x5) => Sub { x, y },
+        (0x8, _, _, 0x6) => Shr { x, y },
+        (0x8, _, _, 0x7) => Subn { x, y },
+        (0x8, _, _, 0xE) => Shl { x, y },
+        (0x9, _, _, 0x0) => SneReg { x, y },
+        (0xA, ..) => LdI(address),
+        (0xB, ..) => JpV0(address),
+        (0xC, ..) => Rnd { x, byte },
+        (0xD, ..) => Drw { x, y, n },
+        (0xE, _, 0x9, 0xE) => Skp { x },
+        (0xE, _, 0xA, 0x1) => Sknp { x },
+        (0xF, 0x0, 0x0, 0x0) => LdILong,
+        (0xF, _, 0x0, 0x1) => Plane(x),
+        (0xF, 0x0, 0x0, 0x2) => Audio,
+        (0xF, _, 0x0, 0x7) => LdVxDt { x },
+        (0xF, _, 0x0, 0xA) => LdVxK { x },
+        (0xF, _, 0x1, 0x5) => LdDtVx { x },
+        (0xF, _, 0x1, 0x8) => LdStVx { x },
+        (0xF, _, 0x1, 0xE) => AddI { x },
+        (0xF, _, 0x2, 0x9) => LdF { x },
+        (0xF, _, 0x3, 0x0) => LdHf { x },
+        (0xF, _, 0x3, 0x3) => LdB { x },
+        (0xF, _, 0x3, 0xA) => Pitch { x },
+        (0xF, _, 0x5, 0x5) => LdIVx { x },
+        (0xF, _, 0x6, 0x5) => LdVxI { x },
+        (0xF, _, 0x7, 0x5) => LdRVx { x },
+        (0xF, _, 0x8, 0x5) => LdVxR { x },
+        _ => return Err(DecodeError { opcode }),
+    };
+
+    Ok(instruction)
+}
+
+impl Instruction {
+    /// The opcode `decode` turns into this instruction. Operands are masked to
+    /// the bits the opcode has room for.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |high: u16, x: u8, y: u8, low: u16| {
+            high << 12 | ((x & 0xF) as u16) << 8 | ((y & 0xF) as u16) << 4 | low
+        };
+        let xnn = |high: u16, x: u8, byte: u8| high << 12 | ((x & 0xF) as u16) << 8 | byte as u16;
+        let fx = |x: u8, low: u16| 0xF000 | ((x & 0xF) as u16) << 8 | low;
+
+        match *self {
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n & 0xF) as u16,
+            ScrollUp(n) => 0x00D0 | (n & 0xF) as u16,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jp(address) => 0x1000 | address & 0x0FFF,
+            Call(address) => 0x2000 | address & 0x0FFF,
+            SeByte { x, byte } => xnn(0x3, x, byte),
+            SneByte { x, byte } => xnn(0x4, x, byte),
+            SeReg { x, y } => xy(0x5, x, y, 0x0),
+            SaveRange { x, y } => xy(0x5, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5, x, y, 0x3),
+            LdByte { x, byte } => xnn(0x6, x, byte),
+            AddByte { x, byte } => xnn(0x7, x, byte),
+            LdReg { x, y } => xy(0x8, x, y, 0x0),
+            Or { x, y } => xy(0x8, x, y, 0x1),
+            And { x, y } => xy(0x8, x, y, 0x2),
+            Xor { x, y } => xy(0x8, x, y, 0x3),
+            AddReg { x, y } => xy(0x8, x, y, 0x4),
+            Sub { x, y } => xy(0x8, x, y, 0x5),
+            Shr { x, y } => xy(0x8, x, y, 0x6),
+            Subn { x, y } => xy(0x8, x, y, 0x7),
+            Shl { x, y } => xy(0x8, x, y, 0xE),
+            SneReg { x, y } => xy(0x9, x, y, 0x0),
+            LdI(address) => 0xA000 | address & 0x0FFF,
+            JpV0(address) => 0xB000 | address & 0x0FFF,
+            Rnd { x, byte } => xnn(0xC, x, byte),
+            Drw { x, y, n } => xy(0xD, x, y, (n & 0xF) as u16),
+            Skp { x } => xnn(0xE, x, 0x9E),
+            Sknp { x } => xnn(0xE, x, 0xA1),
+            LdILong => 0xF000,
+            Plane(planes) => fx(planes, 0x01),
+            Audio => 0xF002,
+            LdVxDt { x } => fx(x, 0x07),
+            LdVxK { x } => fx(x, 0x0A),
+            LdDtVx { x } => fx(x, 0x15),
+            LdStVx { x } => fx(x, 0x18),
+            AddI { x } => fx(x, 0x1E),
+            LdF { x } => fx(x, 0x29),
+            LdHf { x } => fx(x, 0x30),
+            LdB { x } => fx(x, 0x33),
+            Pitch { x } => fx(x, 0x3A),
+            LdIVx { x } => fx(x, 0x55),
+            LdVxI { x } => fx(x, 0x65),
+            LdRVx { x } => fx(x, 0x75),
+            LdVxR { x } => fx(x, 0x85),
+        }
+    }
+
+    /// The earliest instruction set with this instruction. A VM running an
+    /// older set treats it as an unknown opcode.
+    pub fn instruction_set(&self) -> InstructionSet {
+        use Instruction::*;
+
+        match self {
+            ScrollDown(_)
+            | ScrollRight
+            | ScrollLeft
+            | Exit
+            | LowRes
+            | HighRes
+            | LdHf { .. }
+            | LdRVx { .. }
+            | LdVxR { .. } => InstructionSet::SuperChip,
+            ScrollUp(_)
+            | SaveRange { .. }
+            | LoadRange { .. }
+            | LdILong
+            | Plane(_)
+            | Audio
+            | Pitch { .. } => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    /// Size in bytes, including the address word that follows F000
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+}
@@ -1,51 +1,358 @@
-pub(self) mod font;
-#[cfg(test)]
+//! CHIP-8, SUPER-CHIP and XO-CHIP interpreter.
+//!
+//! Without the default `std` feature the crate is `no_std` and never
+//! allocates. The interpreter and loading save states stay available; writing
+//! save states, rewind, the debugger, the ROM database and the tools built on
+//! `String` need `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::ops::{Deref, Range};
+
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+mod debugger;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod divergence;
+mod error;
+mod font;
+mod hooks;
+mod instruction;
+#[cfg(feature = "std")]
+mod json;
+mod load;
+mod platform;
+mod quirks;
+mod random;
+#[cfg(feature = "std")]
+mod rewind;
+mod save_state;
+#[cfg(feature = "std")]
+mod sha1;
+#[cfg(all(test, feature = "std"))]
 mod test;
+#[cfg(feature = "std")]
+pub mod trace;
+
+#[cfg(feature = "std")]
+pub use debugger::{Debugger, StopReason, WatchKind};
+pub use error::VmError;
+pub use font::Font;
+pub use hooks::Hooks;
+pub use instruction::{decode, DecodeError, Instruction};
+pub use load::{LoadError, LoadOptions};
+pub use platform::Platform;
+pub use quirks::{IndexIncrement, Quirks};
+pub use random::{CosmacVipRandom, RandomSource, SeededRandom, DEFAULT_SEED};
+#[cfg(feature = "std")]
+pub use rewind::Rewind;
+pub use save_state::{state_len, SaveStateError, SAVE_STATE_VERSION};
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+pub const PLANE_COUNT: usize = 2;
 pub const PROGAM_COUNTER_START: u16 = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+const DEFAULT_PITCH: u8 = 64;
+
+// One bit per pixel, the leftmost column in the top bit. Low resolution only
+// uses the top 64 bits of the first 32 rows.
+type Plane = [u128; HIRES_SCREEN_HEIGHT];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    BlockedOnKeyPress,
+    WaitingForVblank,
+    Exited,
+}
+
+/// Ordered so each set compares greater than the sets it extends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum InstructionSet {
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: hi-res mode, scrolling, big font, 16x16 sprites and RPL flags
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and audio patterns
+    XoChip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timers {
+    pub delay: u8,
+    pub sound: u8,
+}
+
+/// Part of the screen, in pixels of the current resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    fn union(self, other: DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+// The columns lit in a plane row, as a one row high rectangle
+fn changed_columns(bits: u128, row: usize) -> Option<DirtyRect> {
+    (bits != 0).then(|| DirtyRect {
+        x: bits.leading_zeros() as usize,
+        y: row,
+        width: (HIRES_SCREEN_WIDTH as u32 - bits.leading_zeros() - bits.trailing_zeros()) as usize,
+        height: 1,
+    })
+}
+
 #[derive(Debug, PartialEq, Eq)]
-pub struct VirtualMachine {
-    memory: [u8; 4096],
+pub struct VirtualMachine<R: RandomSource = SeededRandom> {
+    memory: [u8; XO_CHIP_MEMORY_SIZE],
     registers: [u8; 16],
     stack: [u16; 16],
     stack_pointer: u8,
-    screen: [u8; SCREEN_HEIGHT * SCREEN_WIDTH],
+    /// Calls that may be in progress at once, at most the 16 `stack` holds
+    stack_depth: u8,
+    screen: [Plane; PLANE_COUNT],
+    high_resolution: bool,
+    /// Screen changes not yet handed out by `take_frame`
+    dirty: Option<DirtyRect>,
+    selected_planes: u8,
+    rpl_flags: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
     index_register: u16,
     program_counter: u16,
     delay_timer: u8,
     sound_timer: u8,
     pub key_state: [bool; 16],
     pub blocked_on_key_press: bool,
+    waiting_for_vblank: bool,
+    exited: bool,
+    instruction_set: InstructionSet,
+    quirks: Quirks,
+    font: Font,
+    rng: R,
 }
 
 impl VirtualMachine {
     pub fn new() -> VirtualMachine {
-        let mut memory = [0_u8; 4096];
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> VirtualMachine {
+        Self::with_config(InstructionSet::Chip8, quirks)
+    }
 
-        font::copy_font_to_beginning(&mut memory);
+    pub fn with_config(instruction_set: InstructionSet, quirks: Quirks) -> VirtualMachine {
+        Self::with_random_source(instruction_set, quirks, SeededRandom::default())
+    }
+}
+
+impl<R: RandomSource> VirtualMachine<R> {
+    pub fn with_random_source(
+        instruction_set: InstructionSet,
+        quirks: Quirks,
+        rng: R,
+    ) -> VirtualMachine<R> {
+        let mut memory = [0_u8; XO_CHIP_MEMORY_SIZE];
+
+        font::copy_font_to_beginning(&mut memory, Font::default());
 
         VirtualMachine {
             memory,
             registers: [0; 16],
             stack: [0; 16],
             stack_pointer: 0,
-            screen: [0; SCREEN_HEIGHT * SCREEN_WIDTH],
+            stack_depth: 16,
+            screen: [[0; HIRES_SCREEN_HEIGHT]; PLANE_COUNT],
+            high_resolution: false,
+            dirty: Some(DirtyRect {
+                x: 0,
+                y: 0,
+                width: SCREEN_WIDTH,
+                height: SCREEN_HEIGHT,
+            }),
+            selected_planes: 1,
+            rpl_flags: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: DEFAULT_PITCH,
             index_register: 0,
             program_counter: PROGAM_COUNTER_START,
             delay_timer: 0,
             sound_timer: 0,
             key_state: [false; 16],
             blocked_on_key_press: false,
+            waiting_for_vblank: false,
+            exited: false,
+            instruction_set,
+            quirks,
+            font: Font::default(),
+            rng,
+        }
+    }
+
+    pub fn random_source(&self) -> &R {
+        &self.rng
+    }
+
+    pub fn random_source_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    pub fn instruction_set(&self) -> InstructionSet {
+        self.instruction_set
+    }
+
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+    }
+
+    fn has_superchip(&self) -> bool {
+        self.instruction_set != InstructionSet::Chip8
+    }
+
+    fn has_xo_chip(&self) -> bool {
+        self.instruction_set == InstructionSet::XoChip
+    }
+
+    pub fn memory_size(&self) -> usize {
+        if self.has_xo_chip() {
+            XO_CHIP_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        self.registers[(register & 0xF) as usize] = value;
+    }
+
+    /// All memory the current instruction set can address
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.memory_size()]
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        let memory_size = self.memory_size();
+        &mut self.memory[..memory_size]
+    }
+
+    /// Return addresses of the calls in progress, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+
+    /// Replaces the calls in progress. Panics with more than 16 entries.
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        assert!(
+            stack.len() <= self.stack.len(),
+            "the stack holds 16 entries"
+        );
+        self.stack[..stack.len()].copy_from_slice(stack);
+        self.stack[stack.len()..].fill(0);
+        self.stack_pointer = stack.len() as u8;
+    }
+
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    pub fn stack_depth(&self) -> u8 {
+        self.stack_depth
+    }
+
+    /// Limits how many calls may be in progress, from 1 to 16. Calls already
+    /// past a new, lower limit can still return.
+    pub fn set_stack_depth(&mut self, depth: u8) {
+        assert!(
+            (1..=self.stack.len()).contains(&(depth as usize)),
+            "the stack holds 1 to 16 entries"
+        );
+        self.stack_depth = depth;
+    }
+
+    pub fn font(&self) -> Font {
+        self.font
+    }
+
+    /// Switches the hex digits FX29 points at, overwriting them in memory
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+        font::copy_font_to_beginning(&mut self.memory, font);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.program_counter = pc;
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index_register = index;
+    }
+
+    pub fn timers(&self) -> Timers {
+        Timers {
+            delay: self.delay_timer,
+            sound: self.sound_timer,
         }
     }
 
+    pub fn set_timers(&mut self, timers: Timers) {
+        self.delay_timer = timers.delay;
+        self.sound_timer = timers.sound;
+    }
+
     fn reset(&mut self) {
         self.clear_key_state();
         self.clear_memory();
-        self.clear_screen();
+        self.screen = [[0; HIRES_SCREEN_HEIGHT]; PLANE_COUNT];
+        self.high_resolution = false;
+        self.mark_screen_dirty();
+        self.selected_planes = 1;
+        self.audio_pattern.fill(0);
+        self.pitch = DEFAULT_PITCH;
         self.blocked_on_key_press = false;
+        self.waiting_for_vblank = false;
+        self.exited = false;
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.program_counter = PROGAM_COUNTER_START;
@@ -53,16 +360,7 @@ impl VirtualMachine {
         self.stack_pointer = 0;
         self.stack.fill(0);
         self.registers.fill(0);
-        font::copy_font_to_beginning(&mut self.memory);
-    }
-
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.reset();
-
-        let free_space = &mut self.memory[(PROGAM_COUNTER_START as usize)..];
-        for (byte, address) in rom.iter().zip(free_space) {
-            *address = *byte;
-        }
+        font::copy_font_to_beginning(&mut self.memory, self.font);
     }
 
     fn clear_memory(&mut self) {
@@ -70,417 +368,724 @@ impl VirtualMachine {
     }
 
     fn clear_screen(&mut self) {
-        self.screen.fill(0);
+        let previous = self.screen;
+        for plane in self.selected_plane_indices() {
+            self.screen[plane].fill(0);
+        }
+        self.mark_changes(&previous);
     }
 
-    pub fn execute_instruction(&mut self) {
-        if !self.blocked_on_key_press {
-            let opcode = self.fetch_opcode();
+    pub fn execute_instruction(&mut self) -> Result<StepOutcome, VmError> {
+        self.execute_instruction_with(&mut ())
+    }
+
+    /// `execute_instruction`, reporting what the instruction does to `hooks`
+    pub fn execute_instruction_with<H: Hooks>(
+        &mut self,
+        hooks: &mut H,
+    ) -> Result<StepOutcome, VmError> {
+        if self.blocked_on_key_press {
+            return Ok(StepOutcome::BlockedOnKeyPress);
+        }
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+
+        let pc = self.program_counter;
+        let opcode = self.fetch_opcode()?;
+        hooks.on_instruction(pc, opcode);
+
+        let instruction = decode(opcode)
+            .ok()
+            .filter(|instruction| instruction.instruction_set() <= self.instruction_set)
+            .ok_or(VmError::UnknownOpcode { pc, opcode })?;
+        self.execute(instruction, opcode, hooks)?;
 
-            self.execute_opcode(opcode);
+        if self.program_counter as usize + 1 >= self.memory_size() {
+            return Err(VmError::PcOutOfBounds { pc, opcode });
         }
+
+        Ok(StepOutcome::Executed)
     }
 
-    fn fetch_opcode(&self) -> u16 {
+    fn fetch_opcode(&self) -> Result<u16, VmError> {
         let pc: usize = self.program_counter.into();
+        let memory = &self.memory[..self.memory_size()];
+
+        match (memory.get(pc), memory.get(pc + 1)) {
+            (Some(&high), Some(&low)) => Ok((high as u16) << 8 | low as u16),
+            (high, _) => Err(VmError::PcOutOfBounds {
+                pc: self.program_counter,
+                opcode: high.map_or(0, |&high| (high as u16) << 8),
+            }),
+        }
+    }
 
-        (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16
+    fn memory_range(&self, opcode: u16, start: usize, len: usize) -> Result<Range<usize>, VmError> {
+        let end = start + len;
+        let memory_size = self.memory_size();
+
+        if end > memory_size {
+            Err(VmError::MemoryOutOfBounds {
+                pc: self.program_counter,
+                opcode,
+                address: start.max(memory_size),
+            })
+        } else {
+            Ok(start..end)
+        }
     }
 
-    fn execute_opcode(&mut self, opcode: u16) {
-        match opcode & 0xF000 {
-            0x0000 => match opcode & 0x000F {
-                0x0000 => {
-                    // 00E0: Clear display
-                    self.clear_screen();
-                    self.program_counter += 2;
-                }
-                0x000E => {
-                    // 00EE
-                    self.program_counter = self.stack[self.stack_pointer as usize - 1];
-                    self.stack_pointer -= 1;
+    fn next_instruction(&mut self) {
+        self.program_counter = self.program_counter.wrapping_add(2);
+    }
 
-                    self.program_counter += 2;
+    fn skip_next_instruction(&mut self) {
+        self.next_instruction();
+
+        // XO-CHIP skips hop over the whole of the four byte F000 NNNN
+        if self.has_xo_chip() && self.fetch_opcode() == Ok(0xF000) {
+            self.next_instruction();
+        }
+        self.next_instruction();
+    }
+
+    fn execute<H: Hooks>(
+        &mut self,
+        instruction: Instruction,
+        opcode: u16,
+        hooks: &mut H,
+    ) -> Result<(), VmError> {
+        use Instruction::*;
+
+        match instruction {
+            Cls => {
+                self.clear_screen();
+                self.next_instruction();
+            }
+            Ret => {
+                if self.stack_pointer == 0 {
+                    return Err(VmError::StackUnderflow {
+                        pc: self.program_counter,
+                        opcode,
+                    });
                 }
-                _ => panic!("Unknown opcode: {:X}", opcode),
-            },
-            0x1000 => {
-                // 1NNN
-                let address = opcode & 0x0FFF;
+                let Some(&return_address) = self.stack.get(self.stack_pointer as usize - 1)
+                else {
+                    return Err(VmError::StackOverflow {
+                        pc: self.program_counter,
+                        opcode,
+                    });
+                };
+
+                self.program_counter = return_address.wrapping_add(2);
+                self.stack_pointer -= 1;
+            }
+            ScrollDown(n) => {
+                self.scroll_down(n as usize);
+                self.next_instruction();
+            }
+            ScrollUp(n) => {
+                self.scroll_up(n as usize);
+                self.next_instruction();
+            }
+            ScrollRight => {
+                self.scroll_right(4);
+                self.next_instruction();
+            }
+            ScrollLeft => {
+                self.scroll_left(4);
+                self.next_instruction();
+            }
+            Exit => {
+                self.exited = true;
+            }
+            LowRes | HighRes => {
+                self.high_resolution = instruction == HighRes;
+                self.screen = [[0; HIRES_SCREEN_HEIGHT]; PLANE_COUNT];
+                self.mark_screen_dirty();
+                self.next_instruction();
+            }
+            Jp(address) => {
                 self.program_counter = address;
             }
-            0x2000 => {
-                // 2NNN
+            Call(address) => {
+                if self.stack_pointer >= self.stack_depth {
+                    return Err(VmError::StackOverflow {
+                        pc: self.program_counter,
+                        opcode,
+                    });
+                }
                 self.stack[self.stack_pointer as usize] = self.program_counter;
                 self.stack_pointer += 1;
 
-                let address = opcode & 0x0FFF;
                 self.program_counter = address;
             }
-            0x3000 => {
-                // 3XNN
-                let register_idx = Self::get_register_x(opcode);
-                let value = (opcode & 0x00FF) as u8;
-
-                if self.registers[register_idx] == value {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
-                }
+            SeByte { x, byte } => {
+                self.skip_if(self.registers[x as usize] == byte);
             }
-            0x4000 => {
-                // 4XNN
-                let register_x = Self::get_register_x(opcode);
-                let value = (opcode & 0x00FF) as u8;
-
-                if self.registers[register_x] != value {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
+            SneByte { x, byte } => {
+                self.skip_if(self.registers[x as usize] != byte);
+            }
+            SeReg { x, y } => {
+                self.skip_if(self.registers[x as usize] == self.registers[y as usize]);
+            }
+            SaveRange { x, y } => {
+                let registers = Self::get_register_range(x, y);
+                let range =
+                    self.memory_range(opcode, self.index_register as usize, registers.len())?;
+                for (address, register) in range.zip(registers) {
+                    self.memory[address] = self.registers[register];
+                    hooks.on_mem_write(address as u16, self.memory[address]);
                 }
+
+                self.next_instruction();
             }
-            0x5000 => {
-                // 5XY0
-                let register_x = Self::get_register_x(opcode);
-                let register_y = Self::get_register_y(opcode);
+            LoadRange { x, y } => {
+                let registers = Self::get_register_range(x, y);
+                let range =
+                    self.memory_range(opcode, self.index_register as usize, registers.len())?;
+                for (address, register) in range.zip(registers) {
+                    self.registers[register] = self.memory[address];
+                    hooks.on_mem_read(address as u16, self.memory[address]);
+                }
 
-                if self.registers[register_x] == self.registers[register_y] {
-                    self.program_counter += 4;
-                } else {
-                    self.program_counter += 2;
+                self.next_instruction();
+            }
+            LdByte { x, byte } => {
+                self.registers[x as usize] = byte;
+                self.next_instruction();
+            }
+            AddByte { x, byte } => {
+                let vx = &mut self.registers[x as usize];
+                *vx = vx.wrapping_add(byte);
+                self.next_instruction();
+            }
+            LdReg { x, y } => {
+                self.registers[x as usize] = self.registers[y as usize];
+                self.next_instruction();
+            }
+            Or { x, y } | And { x, y } | Xor { x, y } => {
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
+                self.registers[x as usize] = match instruction {
+                    Or { .. } => vx | vy,
+                    And { .. } => vx & vy,
+                    _ => vx ^ vy,
+                };
+
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
+
+                self.next_instruction();
             }
-            0x6000 => {
-                // 6XNN
-                let register_x = Self::get_register_x(opcode);
-                let value = (opcode & 0x00FF) as u8;
+            AddReg { x, y } => {
+                let (sum, did_overflow) =
+                    self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+                self.registers[x as usize] = sum;
+                self.registers[0xF] = did_overflow as u8;
 
-                self.registers[register_x] = value;
-                self.program_counter += 2;
+                self.next_instruction();
             }
-            0x7000 => {
-                // 7XNN
-                let register_x = Self::get_register_x(opcode);
-                let value = (opcode & 0x00FF) as u8;
+            Sub { x, y } => {
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
+                self.registers[x as usize] = vx.wrapping_sub(vy);
+                self.registers[0xF] = (vx >= vy) as u8;
 
-                self.registers[register_x] = self.registers[register_x].wrapping_add(value);
-                self.program_counter += 2;
+                self.next_instruction();
             }
-            0x8000 => match opcode & 0x000F {
-                0x0 => {
-                    // 8XY0
-                    self.registers[Self::get_register_x(opcode)] =
-                        self.registers[Self::get_register_y(opcode)];
+            Shr { x, y } => {
+                let source = self.shift_source(x, y);
+                self.registers[x as usize] = source.wrapping_shr(1);
+                self.registers[0xF] = source & 1;
 
-                    self.program_counter += 2;
-                }
-                0x1 => {
-                    // 8XY1
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    self.registers[Self::get_register_x(opcode)] =
-                        vx | self.registers[Self::get_register_y(opcode)];
+                self.next_instruction();
+            }
+            Subn { x, y } => {
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
+                self.registers[x as usize] = vy.wrapping_sub(vx);
+                self.registers[0xF] = (vy >= vx) as u8;
 
-                    self.program_counter += 2;
-                }
-                0x2 => {
-                    // 8XY2
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    self.registers[Self::get_register_x(opcode)] =
-                        vx & self.registers[Self::get_register_y(opcode)];
+                self.next_instruction();
+            }
+            Shl { x, y } => {
+                let source = self.shift_source(x, y);
+                self.registers[x as usize] = source.wrapping_shl(1);
+                self.registers[0xF] = (source >> 7) & 1;
 
-                    self.program_counter += 2;
-                }
-                0x3 => {
-                    // 8XY3
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    self.registers[Self::get_register_x(opcode)] =
-                        vx ^ self.registers[Self::get_register_y(opcode)];
+                self.next_instruction();
+            }
+            SneReg { x, y } => {
+                self.skip_if(self.registers[x as usize] != self.registers[y as usize]);
+            }
+            LdI(address) => {
+                self.index_register = address;
+                self.next_instruction();
+            }
+            JpV0(address) => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.registers[(address >> 8) as usize]
+                } else {
+                    self.registers[0]
+                };
 
-                    self.program_counter += 2;
-                }
-                0x4 => {
-                    // 8XY4
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    let vy = self.registers[Self::get_register_y(opcode)];
-                    let (sum, did_overflow) = vx.overflowing_add(vy);
-                    self.registers[Self::get_register_x(opcode)] = sum;
-
-                    if did_overflow {
-                        self.registers[0xF] = 1;
-                    } else {
-                        self.registers[0xF] = 0;
-                    }
+                self.program_counter = address + offset as u16;
+            }
+            Rnd { x, byte } => {
+                self.registers[x as usize] = self.rng.next_byte() & byte;
+                self.next_instruction();
+            }
+            Drw { x, y, n } => {
+                self.draw_sprite(x, y, n, opcode, hooks)?;
 
-                    self.program_counter += 2;
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
                 }
-                0x5 => {
-                    // 8XY5
 
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    let vy = self.registers[Self::get_register_y(opcode)];
-                    self.registers[Self::get_register_x(opcode)] = vx.wrapping_sub(vy);
+                self.next_instruction();
+            }
+            Skp { x } => {
+                self.skip_if(self.key_state[(self.registers[x as usize] & 0x0F) as usize]);
+            }
+            Sknp { x } => {
+                self.skip_if(!self.key_state[(self.registers[x as usize] & 0x0F) as usize]);
+            }
+            LdILong => {
+                let address = self.memory_range(opcode, self.program_counter as usize + 2, 2)?;
+                self.index_register = (self.memory[address.start] as u16) << 8
+                    | self.memory[address.start + 1] as u16;
 
-                    if vx > vy {
-                        self.registers[0xF] = 1;
-                    } else {
-                        self.registers[0xF] = 0;
-                    }
+                self.next_instruction();
+                self.next_instruction();
+            }
+            Plane(planes) => {
+                self.selected_planes = planes & 0b11;
+                self.next_instruction();
+            }
+            Audio => {
+                let range = self.memory_range(opcode, self.index_register as usize, 16)?;
+                self.audio_pattern
+                    .copy_from_slice(&self.memory[range.clone()]);
+                self.report_reads(range, hooks);
 
-                    self.program_counter += 2;
+                self.next_instruction();
+            }
+            LdVxDt { x } => {
+                self.registers[x as usize] = self.delay_timer;
+                self.next_instruction();
+            }
+            LdVxK { .. } => {
+                self.blocked_on_key_press = true;
+                self.clear_key_state();
+            }
+            LdDtVx { x } => {
+                self.delay_timer = self.registers[x as usize];
+                self.next_instruction();
+            }
+            LdStVx { x } => {
+                let was_playing = self.sound_timer > 0;
+                self.sound_timer = self.registers[x as usize];
+                match (was_playing, self.sound_timer > 0) {
+                    (false, true) => hooks.on_sound_start(),
+                    (true, false) => hooks.on_sound_stop(),
+                    _ => {}
                 }
-                0x6 => {
-                    // 8XY6
+                self.next_instruction();
+            }
+            AddI { x } => {
+                self.index_register = self
+                    .index_register
+                    .wrapping_add(self.registers[x as usize] as u16);
+
+                self.next_instruction();
+            }
+            LdF { x } => {
+                self.index_register = Self::get_sprite_address(self.registers[x as usize]) as u16;
+                self.next_instruction();
+            }
+            LdHf { x } => {
+                self.index_register =
+                    Self::get_big_sprite_address(self.registers[x as usize]) as u16;
+                self.next_instruction();
+            }
+            LdB { x } => {
+                let val = self.registers[x as usize];
+                let i = self
+                    .memory_range(opcode, self.index_register as usize, 3)?
+                    .start;
+
+                self.memory[i + 2] = val % 10;
+                self.memory[i + 1] = (val / 10) % 10;
+                self.memory[i] = val / 100;
+                self.report_writes(i..i + 3, hooks);
+
+                self.next_instruction();
+            }
+            Pitch { x } => {
+                self.pitch = self.registers[x as usize];
+                self.next_instruction();
+            }
+            LdIVx { x } => {
+                let register_x = x as usize;
+                let range =
+                    self.memory_range(opcode, self.index_register as usize, register_x + 1)?;
+                self.memory[range.clone()].copy_from_slice(&self.registers[..=register_x]);
+                self.report_writes(range, hooks);
+
+                self.advance_index_after_load_store(register_x);
+                self.next_instruction();
+            }
+            LdVxI { x } => {
+                let register_x = x as usize;
+                let range =
+                    self.memory_range(opcode, self.index_register as usize, register_x + 1)?;
+                self.registers[..=register_x].copy_from_slice(&self.memory[range.clone()]);
+                self.report_reads(range, hooks);
+
+                self.advance_index_after_load_store(register_x);
+                self.next_instruction();
+            }
+            LdRVx { x } => {
+                let register_x = x as usize;
+                self.rpl_flags[..=register_x].copy_from_slice(&self.registers[..=register_x]);
+                self.next_instruction();
+            }
+            LdVxR { x } => {
+                let register_x = x as usize;
+                self.registers[..=register_x].copy_from_slice(&self.rpl_flags[..=register_x]);
+                self.next_instruction();
+            }
+        }
 
-                    let vy = self.registers[Self::get_register_y(opcode)]; // shift quirk, TODO: make configurable
-                    self.registers[Self::get_register_x(opcode)] = vy.wrapping_shr(1);
-                    self.registers[0xF] = vy & 1;
+        Ok(())
+    }
 
-                    self.program_counter += 2;
-                }
-                0x7 => {
-                    // 8XY7
-                    let vx = self.registers[Self::get_register_x(opcode)];
-                    let vy = self.registers[Self::get_register_y(opcode)];
-                    self.registers[Self::get_register_x(opcode)] = vy.wrapping_sub(vx);
-
-                    if vy >= vx {
-                        self.registers[0xF] = 1;
-                    } else {
-                        self.registers[0xF] = 0;
-                    }
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.skip_next_instruction();
+        } else {
+            self.next_instruction();
+        }
+    }
 
-                    self.program_counter += 2;
+    // DXYN, or DXY0 for a 16x16 sprite on SUPER-CHIP and XO-CHIP
+    fn draw_sprite<H: Hooks>(
+        &mut self,
+        x: u8,
+        y: u8,
+        n: u8,
+        opcode: u16,
+        hooks: &mut H,
+    ) -> Result<(), VmError> {
+        let vx = self.registers[x as usize] as usize;
+        let vy = self.registers[y as usize] as usize;
+        let (height, bytes_per_row) = self.sprite_shape(n);
+        let planes = self.selected_plane_indices();
+        let sprite_size = height * bytes_per_row;
+        let sprites = self.memory_range(
+            opcode,
+            self.index_register as usize,
+            sprite_size * planes.clone().count(),
+        )?;
+        self.report_reads(sprites.clone(), hooks);
+
+        self.registers[0xF] = 0;
+
+        let (screen_width, screen_height) = (self.screen_width(), self.screen_height());
+        let origin_row = vy % screen_height;
+        let origin_col = vx % screen_width;
+        let clip = self.quirks.clip_sprites;
+        let visible = self.row_mask();
+
+        for (plane, sprite_start) in planes.zip(sprites.step_by(sprite_size.max(1))) {
+            let sprite = sprite_start..sprite_start + sprite_size;
+            for (y, address) in sprite.step_by(bytes_per_row).enumerate() {
+                let bits = self.memory[address..address + bytes_per_row]
+                    .iter()
+                    .fold(0_u128, |bits, byte| bits << 8 | *byte as u128)
+                    << (128 - 8 * bytes_per_row);
+
+                let mut row = origin_row + y;
+                if row >= screen_height {
+                    if clip {
+                        break;
+                    }
+                    row %= screen_height;
                 }
-                0xE => {
-                    // 8XYE
 
-                    let vy = self.registers[Self::get_register_y(opcode)]; // shift quirk, TODO: make configurable
-                    self.registers[Self::get_register_x(opcode)] = vy.wrapping_shl(1);
-                    self.registers[0xF] = (vy >> 7) & 1;
+                let mut pixels = bits >> origin_col & visible;
+                if !clip {
+                    // What went past the right edge comes back in on the left
+                    pixels |= bits
+                        .checked_shl((screen_width - origin_col) as u32)
+                        .unwrap_or(0);
+                }
 
-                    self.program_counter += 2;
+                let screen_row = &mut self.screen[plane][row];
+                if *screen_row & pixels != 0 {
+                    self.registers[0xF] = 1;
                 }
-                _ => panic!("Unknown opcode: {:X}", opcode),
-            },
-            0x9000 => {
-                // 9XY0
-                let register_x = Self::get_register_x(opcode);
-                let register_y = Self::get_register_y(opcode);
-
-                if self.registers[register_x] != self.registers[register_y] {
-                    self.program_counter += 4
-                } else {
-                    self.program_counter += 2;
+                *screen_row ^= pixels;
+
+                if let Some(rect) = changed_columns(pixels, row) {
+                    self.mark_dirty(rect);
                 }
             }
-            0xA000 => {
-                // ANNN
-                let address = opcode & 0x0FFF;
-                self.index_register = address;
+        }
 
-                self.program_counter += 2;
-            }
-            0xB000 => {
-                // BNNN
-                let address = opcode & 0x0FFF;
-                let v0 = self.registers[0] as u16;
-                self.index_register = address + v0;
+        hooks.on_draw(vx as u8, vy as u8, n, self.registers[0xF] == 1);
+        Ok(())
+    }
 
-                self.program_counter += 2;
-            }
-            0xC000 => {
-                // CXNN
-                let register_x = Self::get_register_x(opcode);
-                let random_byte = fastrand::u8(..);
+    fn report_reads<H: Hooks>(&self, range: Range<usize>, hooks: &mut H) {
+        for address in range {
+            hooks.on_mem_read(address as u16, self.memory[address]);
+        }
+    }
 
-                self.registers[register_x] = random_byte & ((opcode & 0x00FF) as u8);
+    fn report_writes<H: Hooks>(&self, range: Range<usize>, hooks: &mut H) {
+        for address in range {
+            hooks.on_mem_write(address as u16, self.memory[address]);
+        }
+    }
 
-                self.program_counter += 2;
-            }
-            0xD000 => {
-                // DXYN
-                let vx = self.registers[Self::get_register_x(opcode)] as usize;
-                let vy = self.registers[Self::get_register_y(opcode)] as usize;
-                let height = (opcode & 0x000F) as usize;
+    // Rows and bytes per row of the sprite DXYN draws on each plane
+    fn sprite_shape(&self, n: u8) -> (usize, usize) {
+        match n {
+            0 if self.has_superchip() => (16, 2),
+            height => (height as usize, 1),
+        }
+    }
 
-                self.registers[0xF] = 0;
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[y as usize]
+        } else {
+            self.registers[x as usize]
+        }
+    }
 
-                let wrap = vx >= SCREEN_WIDTH || vy >= SCREEN_HEIGHT;
+    fn advance_index_after_load_store(&mut self, register_x: usize) {
+        match self.quirks.load_store_index {
+            IndexIncrement::None => {}
+            IndexIncrement::X => {
+                self.index_register = self.index_register.wrapping_add(register_x as u16)
+            }
+            IndexIncrement::XPlusOne => {
+                self.index_register = self.index_register.wrapping_add(register_x as u16 + 1)
+            }
+        }
+    }
 
-                for (y, mut row) in (vy..(vy + height)).enumerate() {
-                    let byte = self.memory[self.index_register as usize + y];
+    fn get_sprite_address(sprite_id: u8) -> u8 {
+        (sprite_id & 0x0F) * 5
+    }
 
-                    if wrap {
-                        row %= SCREEN_HEIGHT;
-                    } else if row >= SCREEN_HEIGHT {
-                        break;
-                    }
+    fn get_big_sprite_address(sprite_id: u8) -> u8 {
+        font::BIG_FONT_START as u8 + (sprite_id & 0x0F) * 10
+    }
+
+    pub fn complete_fx0a(&mut self, key_value: u8) {
+        let Ok(opcode) = self.fetch_opcode() else {
+            return;
+        };
+        let register_x = Self::get_register_x(opcode);
 
-                    for (x, mut col) in (vx..(vx + 8)).enumerate() {
-                        if wrap {
-                            col %= SCREEN_WIDTH
-                        } else if col >= SCREEN_WIDTH {
-                            break;
-                        }
-                        let pixel = self.get_pixel_mut(row, col);
-                        let sprite_value = byte & (0x80 >> x);
+        self.registers[register_x] = key_value;
 
-                        let old_pixel = *pixel;
+        self.blocked_on_key_press = false;
+        self.next_instruction();
+    }
 
-                        if sprite_value != 0 {
-                            *pixel ^= 1;
+    fn get_register_x(opcode: u16) -> usize {
+        ((opcode & 0x0F00) >> 8) as usize
+    }
 
-                            if old_pixel == 1 && *pixel == 0 {
-                                self.registers[0xF] = 1;
-                            }
-                        }
-                    }
-                }
+    // 5XY2 and 5XY3 walk from X to Y, backwards if Y is smaller
+    fn get_register_range(x: u8, y: u8) -> impl ExactSizeIterator<Item = usize> {
+        let (register_x, register_y) = (x as usize, y as usize);
+        let count = register_x.abs_diff(register_y) + 1;
 
-                self.program_counter += 2;
+        (0..count).map(move |step| {
+            if register_x <= register_y {
+                register_x + step
+            } else {
+                register_x - step
             }
-            0xE000 => match opcode & 0x00FF {
-                0x009E => {
-                    // EX9E
-                    let register_x = Self::get_register_x(opcode);
-                    let vx = self.registers[register_x];
+        })
+    }
 
-                    if self.key_state[vx as usize] {
-                        self.program_counter += 4;
-                    } else {
-                        self.program_counter += 2;
-                    }
-                }
-                0x00A1 => {
-                    // EXA1
-                    let register_x = Self::get_register_x(opcode);
-                    let vx = self.registers[register_x];
-
-                    if !self.key_state[vx as usize] {
-                        self.program_counter += 4;
-                    } else {
-                        self.program_counter += 2;
-                    }
-                }
-                _ => panic!("Unknown opcode: {:X}", opcode),
-            },
-            0xF000 => match opcode & 0x000F {
-                0x0007 => {
-                    // FX07
-                    let register_x = Self::get_register_x(opcode);
-                    self.registers[register_x] = self.delay_timer;
-
-                    self.program_counter += 2;
-                }
-                0x000A => {
-                    // FX0A
-                    self.blocked_on_key_press = true;
-                    self.clear_key_state();
-                }
-                0x0005 => match opcode & 0x00F0 {
-                    0x0010 => {
-                        // FX15
-                        let register_x = Self::get_register_x(opcode);
-                        self.delay_timer = self.registers[register_x];
+    pub fn is_high_resolution(&self) -> bool {
+        self.high_resolution
+    }
 
-                        self.program_counter += 2;
-                    }
-                    0x0050 => {
-                        // FX55
-                        let register_x = Self::get_register_x(opcode);
-                        let i = self.index_register as usize;
-                        for (idx, mem) in self.memory[i..=(i + register_x)].iter_mut().enumerate() {
-                            *mem = self.registers[idx];
-                        }
+    pub fn screen_width(&self) -> usize {
+        if self.high_resolution {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
 
-                        self.index_register += (register_x + 1) as u16; // Memory quirk, TODO: Make Configurable
+    pub fn screen_height(&self) -> usize {
+        if self.high_resolution {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
 
-                        self.program_counter += 2;
-                    }
-                    0x0060 => {
-                        // FX65
-                        let register_x = Self::get_register_x(opcode);
-                        let i = self.index_register as usize;
-                        for (idx, mem) in self.memory[i..=(i + register_x)].iter().enumerate() {
-                            self.registers[idx] = *mem;
-                        }
+    /// Bounding box of the screen changes since the last call, `None` when
+    /// nothing changed. Resets, resolution switches and loaded states count
+    /// as changing the whole screen.
+    pub fn take_frame(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
 
-                        self.index_register += (register_x + 1) as u16; // Memory quirk, TODO: Make Configurable
+    fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.dirty = Some(self.dirty.map_or(rect, |dirty| dirty.union(rect)));
+    }
 
-                        self.program_counter += 2;
-                    }
-                    _ => panic!("Unknown opcode: {:X}", opcode),
-                },
-                0x0008 => {
-                    // FX18
-                    let register_x = Self::get_register_x(opcode);
-                    self.sound_timer = self.registers[register_x];
-
-                    self.program_counter += 2;
-                }
-                0x000E => {
-                    // FX1E
-                    let register_x = Self::get_register_x(opcode);
-                    self.index_register += self.registers[register_x] as u16;
+    fn mark_screen_dirty(&mut self) {
+        self.dirty = Some(DirtyRect {
+            x: 0,
+            y: 0,
+            width: self.screen_width(),
+            height: self.screen_height(),
+        });
+    }
 
-                    self.program_counter += 2;
-                }
-                0x0009 => {
-                    // FX29
-                    let register_x = Self::get_register_x(opcode);
-                    self.index_register =
-                        Self::get_sprite_address(self.registers[register_x]) as u16;
+    // Marks the bounding box of the pixels that differ from `previous`
+    fn mark_changes(&mut self, previous: &[Plane; PLANE_COUNT]) {
+        let visible = self.row_mask();
+        let mut changed = [0; HIRES_SCREEN_HEIGHT];
+        for (now, before) in self.screen.iter().zip(previous) {
+            for ((changed, now), before) in changed.iter_mut().zip(now).zip(before) {
+                *changed |= (now ^ before) & visible;
+            }
+        }
 
-                    self.program_counter += 2;
-                }
-                0x0003 => {
-                    // FX33
-                    let register_x = Self::get_register_x(opcode);
-                    let val = self.registers[register_x];
-                    let i = self.index_register as usize;
+        for (row, &bits) in changed[..self.screen_height()].iter().enumerate() {
+            if let Some(rect) = changed_columns(bits, row) {
+                self.mark_dirty(rect);
+            }
+        }
+    }
 
-                    self.memory[i + 2] = val % 10;
-                    self.memory[i + 1] = (val / 10) % 10;
-                    self.memory[i] = val / 100;
+    // Bits of a plane row that fall on the screen
+    fn row_mask(&self) -> u128 {
+        !0 << (HIRES_SCREEN_WIDTH - self.screen_width())
+    }
 
-                    self.program_counter += 2;
-                }
-                _ => panic!("Unknown opcode: {:X}", opcode),
-            },
-            _ => panic!("Unknown opcode: {:X}", opcode),
+    fn selected_plane_indices(&self) -> impl Iterator<Item = usize> + Clone {
+        let selected_planes = self.selected_planes;
+        (0..PLANE_COUNT).filter(move |plane| selected_planes & 1 << plane != 0)
+    }
+
+    /// The pixel at column `x` of row `y`, with bit `n` set when it is lit on
+    /// plane `n`. Both must be inside the current resolution.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let bit = 1 << (HIRES_SCREEN_WIDTH - 1 - x);
+        (0..PLANE_COUNT)
+            .filter(|&plane| self.screen[plane][y] & bit != 0)
+            .fold(0, |pixel, plane| pixel | 1 << plane)
+    }
+
+    /// Rows of pixels, where each pixel has bit `n` set when it is lit on plane `n`
+    pub fn screen_rows(&self) -> impl Iterator<Item = ScreenRow> + '_ {
+        (0..self.screen_height()).map(move |row| self.screen_row(row))
+    }
+
+    fn screen_row(&self, row: usize) -> ScreenRow {
+        let width = self.screen_width();
+        let mut pixels = [0; HIRES_SCREEN_WIDTH];
+        for (x, pixel) in pixels[..width].iter_mut().enumerate() {
+            *pixel = self.pixel(x, row);
         }
+        ScreenRow { pixels, width }
     }
 
-    fn get_sprite_address(sprite_id: u8) -> u8 {
-        debug_assert!(sprite_id <= 0x0F);
+    /// Rows of pixels lit on `plane`, which is below `PLANE_COUNT`
+    pub fn plane_rows(
+        &self,
+        plane: usize,
+    ) -> impl Iterator<Item = impl Iterator<Item = bool> + '_> {
+        let width = self.screen_width();
+        self.screen[plane][..self.screen_height()]
+            .iter()
+            .map(move |bits| {
+                (0..width).map(move |col| bits << col >> (HIRES_SCREEN_WIDTH - 1) != 0)
+            })
+    }
 
-        sprite_id * 5
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
     }
 
-    pub fn complete_fx0a(&mut self, key_value: u8) {
-        let opcode = self.fetch_opcode();
-        let register_x = Self::get_register_x(opcode);
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
 
-        self.registers[register_x] = key_value;
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
 
-        self.blocked_on_key_press = false;
-        self.program_counter += 2;
+    /// Rate in Hz at which the bits of the audio pattern are played back,
+    /// 4000 * 2^((pitch - 64) / 48)
+    pub fn playback_rate(&self) -> f32 {
+        // Whole octaves are a shift and the rest steps of 2^(1/48), as core
+        // has no powf
+        let steps = self.pitch as i32 - 64;
+        let octaves = steps.div_euclid(48);
+        let fraction = (0..steps.rem_euclid(48)).fold(1.0, |rate: f32, _| rate * 1.014_545_3);
+        let scale = if octaves >= 0 {
+            (1 << octaves) as f32
+        } else {
+            1.0 / (1 << -octaves) as f32
+        };
+        4000.0 * scale * fraction
     }
 
-    fn get_register_x(opcode: u16) -> usize {
-        ((opcode & 0x0F00) >> 8) as usize
+    // Scrolling only moves the selected planes, the others stay where they are.
+    // `source` gives each new row from the plane as it was.
+    fn scroll_planes(&mut self, source: impl Fn(&Plane, usize) -> u128) {
+        let previous = self.screen;
+        for plane in self.selected_plane_indices() {
+            for row in 0..self.screen_height() {
+                self.screen[plane][row] = source(&previous[plane], row);
+            }
+        }
+        self.mark_changes(&previous);
     }
 
-    fn get_register_y(opcode: u16) -> usize {
-        ((opcode & 0x0F0) >> 4) as usize
+    fn scroll_down(&mut self, rows: usize) {
+        self.scroll_planes(|plane, row| row.checked_sub(rows).map_or(0, |row| plane[row]));
     }
 
-    fn get_pixel_mut(&mut self, row: usize, col: usize) -> &mut u8 {
-        &mut self.screen[row * SCREEN_WIDTH + col]
+    fn scroll_up(&mut self, rows: usize) {
+        let height = self.screen_height();
+        self.scroll_planes(|plane, row| {
+            if row + rows < height {
+                plane[row + rows]
+            } else {
+                0
+            }
+        });
+    }
+
+    fn scroll_right(&mut self, cols: usize) {
+        let visible = self.row_mask();
+        self.scroll_planes(|plane, row| plane[row] >> cols & visible);
     }
 
-    pub fn screen_rows(&self) -> impl Iterator<Item = &[u8]> {
-        self.screen.chunks_exact(SCREEN_WIDTH)
+    fn scroll_left(&mut self, cols: usize) {
+        self.scroll_planes(|plane, row| plane[row] << cols);
     }
 
     fn clear_key_state(&mut self) {
@@ -488,8 +1093,54 @@ impl VirtualMachine {
     }
 
     pub fn decrement_timers(&mut self) {
+        self.decrement_timers_with(&mut ())
+    }
+
+    /// `decrement_timers`, telling `hooks` when the sound stops
+    pub fn decrement_timers_with<H: Hooks>(&mut self, hooks: &mut H) {
+        // Timers tick once per frame, which is also the vblank a display-wait DXYN is waiting for
+        self.waiting_for_vblank = false;
+        if self.sound_timer == 1 {
+            hooks.on_sound_stop();
+        }
         self.sound_timer = self.sound_timer.saturating_sub(1);
         self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.rng.tick();
+    }
+}
+
+/// A row of the screen, which derefs to its pixels like the `&[u8]` rows
+/// the screen used to be stored as. It lives on the stack, so reading the
+/// screen never allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenRow {
+    pixels: [u8; HIRES_SCREEN_WIDTH],
+    width: usize,
+}
+
+impl Deref for ScreenRow {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.pixels[..self.width]
+    }
+}
+
+impl IntoIterator for ScreenRow {
+    type Item = u8;
+    type IntoIter = core::iter::Take<core::array::IntoIter<u8, HIRES_SCREEN_WIDTH>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pixels.into_iter().take(self.width)
+    }
+}
+
+impl<'a> IntoIterator for &'a ScreenRow {
+    type Item = &'a u8;
+    type IntoIter = core::slice::Iter<'a, u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
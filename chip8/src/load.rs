@@ -0,0 +1,107 @@
+//! Loading program images into memory.
+
+use core::fmt;
+
+use crate::{RandomSource, VirtualMachine, PROGAM_COUNTER_START};
+
+/// Where a ROM is placed in memory and where execution starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub load_address: u16,
+    pub entry_point: u16,
+}
+
+impl LoadOptions {
+    /// ETI-660 programs start at 0x600
+    pub const ETI_660: LoadOptions = LoadOptions {
+        load_address: 0x600,
+        entry_point: 0x600,
+    };
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            load_address: PROGAM_COUNTER_START,
+            entry_point: PROGAM_COUNTER_START,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The image runs past the end of memory. `available` is the number of
+    /// bytes from `address` to the end of memory.
+    TooLarge {
+        address: u16,
+        len: usize,
+        available: usize,
+    },
+    /// The entry point does not leave room for a whole opcode in memory
+    EntryPointOutOfBounds(u16),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LoadError::TooLarge {
+                address,
+                len,
+                available,
+            } => write!(
+                f,
+                "ROM of {} bytes does not fit at {:03X}, only {} bytes are free",
+                len, address, available
+            ),
+            LoadError::EntryPointOutOfBounds(entry_point) => {
+                write!(f, "Entry point {:03X} is outside of memory", entry_point)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LoadError {}
+
+impl<R: RandomSource> VirtualMachine<R> {
+    /// Resets the VM and loads `rom` at 0x200.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        self.load_rom_with(rom, LoadOptions::default())
+    }
+
+    pub fn load_rom_with(&mut self, rom: &[u8], options: LoadOptions) -> Result<(), LoadError> {
+        self.load_segments(&[(options.load_address, rom)], options.entry_point)
+    }
+
+    /// Resets the VM, copies each segment to its address and starts execution
+    /// at `entry_point`. Later segments overwrite earlier ones where they
+    /// overlap. On error the VM is left untouched.
+    pub fn load_segments(
+        &mut self,
+        segments: &[(u16, &[u8])],
+        entry_point: u16,
+    ) -> Result<(), LoadError> {
+        let memory_size = self.memory_size();
+        for &(address, data) in segments {
+            let available = memory_size.saturating_sub(address as usize);
+            if data.len() > available {
+                return Err(LoadError::TooLarge {
+                    address,
+                    len: data.len(),
+                    available,
+                });
+            }
+        }
+        if entry_point as usize + 1 >= memory_size {
+            return Err(LoadError::EntryPointOutOfBounds(entry_point));
+        }
+
+        self.reset();
+        for &(address, data) in segments {
+            let start = address as usize;
+            self.memory[start..start + data.len()].copy_from_slice(data);
+        }
+        self.program_counter = entry_point;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Font, InstructionSet, Quirks, RandomSource, VirtualMachine};
+
+/// Interpreters a ROM can be written for, each bundling the settings it ran with.
+/// Memory and screen size follow from the instruction set, see
+/// `VirtualMachine::memory_size` and `screen_width`/`screen_height`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    /// SUPER-CHIP 1.1 on the HP 48, which waited for the display to draw
+    SuperChipLegacy,
+    /// SUPER-CHIP as modern interpreters and Octo run it
+    SuperChipModern,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 5] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::SuperChipLegacy,
+        Platform::SuperChipModern,
+        Platform::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "COSMAC VIP",
+            Platform::Chip48 => "CHIP-48",
+            Platform::SuperChipLegacy => "SUPER-CHIP 1.1",
+            Platform::SuperChipModern => "SUPER-CHIP (modern)",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn instruction_set(self) -> InstructionSet {
+        match self {
+            Platform::CosmacVip | Platform::Chip48 => InstructionSet::Chip8,
+            Platform::SuperChipLegacy | Platform::SuperChipModern => InstructionSet::SuperChip,
+            Platform::XoChip => InstructionSet::XoChip,
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::CosmacVip => Quirks::COSMAC_VIP,
+            Platform::Chip48 => Quirks::CHIP_48,
+            Platform::SuperChipLegacy => Quirks {
+                display_wait: true,
+                ..Quirks::SUPER_CHIP
+            },
+            Platform::SuperChipModern => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks {
+                clip_sprites: false,
+                ..Quirks::default()
+            },
+        }
+    }
+
+    /// A speed most programs for the platform expect, at 60 frames a second
+    pub fn instructions_per_frame(self) -> u32 {
+        match self {
+            Platform::CosmacVip => 15,
+            Platform::Chip48 | Platform::SuperChipLegacy | Platform::SuperChipModern => 30,
+            Platform::XoChip => 200,
+        }
+    }
+
+    pub fn font(self) -> Font {
+        match self {
+            Platform::CosmacVip => Font::CosmacVip,
+            _ => Font::Chip48,
+        }
+    }
+
+    /// The VIP kept 12 return addresses, later interpreters 16
+    pub fn stack_depth(self) -> u8 {
+        match self {
+            Platform::CosmacVip => 12,
+            _ => 16,
+        }
+    }
+}
+
+impl VirtualMachine {
+    pub fn with_platform(platform: Platform) -> VirtualMachine {
+        let mut vm = Self::with_config(platform.instruction_set(), platform.quirks());
+        vm.set_platform(platform);
+        vm
+    }
+}
+
+impl<R: RandomSource> VirtualMachine<R> {
+    /// Switches the instruction set, quirks, font and stack depth to the
+    /// platform's. The program in memory and the machine state are kept.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.instruction_set = platform.instruction_set();
+        self.quirks = platform.quirks();
+        self.stack_depth = platform.stack_depth();
+        self.set_font(platform.font());
+    }
+
+    /// The preset the VM is configured as, if any
+    pub fn platform(&self) -> Option<Platform> {
+        Platform::ALL.into_iter().find(|&platform| {
+            platform.instruction_set() == self.instruction_set
+                && platform.quirks() == self.quirks
+                && platform.stack_depth() == self.stack_depth
+                && platform.font() == self.font
+        })
+    }
+}
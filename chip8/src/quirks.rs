@@ -0,0 +1,68 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// FX55/FX65 leave I untouched (SUPER-CHIP)
+    None,
+    /// I is advanced by X (CHIP-48)
+    X,
+    /// I is advanced by X + 1 (COSMAC VIP)
+    XPlusOne,
+}
+
+/// Behaviors that differ between CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// How FX55/FX65 move the index register after the transfer
+    pub load_store_index: IndexIncrement,
+    /// BNNN becomes BXNN and jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 clear VF
+    pub vf_reset: bool,
+    /// DXYN clips sprites at the screen edge instead of wrapping them around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next frame before execution continues
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::X,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::None,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::XPlusOne,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+}
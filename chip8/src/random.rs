@@ -0,0 +1,93 @@
+/// Source of the random bytes CXNN masks.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    /// Snapshot of the generator that save states keep, so a loaded state
+    /// carries on with the same random bytes it would have produced
+    fn state(&self) -> u64;
+
+    fn set_state(&mut self, state: u64);
+
+    /// Called on every 60 Hz timer tick, for generators that run on the clock
+    fn tick(&mut self) {}
+}
+
+pub const DEFAULT_SEED: u64 = 0xC8C8_5EED;
+
+/// SplitMix64, the default source. The same seed always produces the same run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom { state: seed }
+    }
+}
+
+impl Default for SeededRandom {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
+
+/// Modelled on the COSMAC VIP interpreter's generator: the 60 Hz interrupt and
+/// CXNN both bump R9, and CXNN reads the byte R9.0 points at in the
+/// interpreter's own code page (0x0100-0x01FF), adds R9.1 to it and keeps the
+/// sum in R9.1 as the next random byte. The code page is not part of this
+/// crate, so it has to come from a dump of the interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CosmacVipRandom {
+    code_page: [u8; 256],
+    r9: u16,
+}
+
+impl CosmacVipRandom {
+    pub fn new(code_page: [u8; 256]) -> Self {
+        CosmacVipRandom { code_page, r9: 0 }
+    }
+}
+
+impl RandomSource for CosmacVipRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+
+        let [high, low] = self.r9.to_be_bytes();
+        let byte = self.code_page[low as usize].wrapping_add(high);
+        self.r9 = u16::from_be_bytes([byte, low]);
+
+        byte
+    }
+
+    fn state(&self) -> u64 {
+        self.r9 as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.r9 = state as u16;
+    }
+
+    fn tick(&mut self) {
+        self.r9 = self.r9.wrapping_add(1);
+    }
+}
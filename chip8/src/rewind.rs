@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use crate::{RandomSource, VirtualMachine};
+
+/// Records a save state every frame so play can be stepped backwards.
+///
+/// Only the newest state is kept whole. Every older frame is stored as the XOR
+/// of itself with the frame after it, run-length encoded. Consecutive frames
+/// rarely differ in more than a few registers, timers and pixels, so a frame
+/// usually costs tens of bytes and several minutes fit in a few megabytes.
+#[derive(Debug)]
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    history: VecDeque<Entry>,
+    history_bytes: usize,
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// XOR of the previous state with the one after it, see `encode_delta`
+    Delta(Vec<u8>),
+    /// Used when the states differ in size, e.g. after switching to XO-CHIP
+    Full(Vec<u8>),
+}
+
+impl Entry {
+    fn len(&self) -> usize {
+        match self {
+            Entry::Delta(bytes) | Entry::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+impl Rewind {
+    /// `capacity` is the number of frames that can be stepped back through
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            newest: None,
+            history: VecDeque::with_capacity(capacity),
+            history_bytes: 0,
+        }
+    }
+
+    pub fn push<R: RandomSource>(&mut self, vm: &VirtualMachine<R>) {
+        let state = vm.save_state();
+
+        if let Some(previous) = self.newest.take() {
+            let entry = if previous.len() == state.len() {
+                Entry::Delta(encode_delta(&previous, &state))
+            } else {
+                Entry::Full(previous)
+            };
+            self.history_bytes += entry.len();
+            self.history.push_back(entry);
+        }
+
+        while self.history.len() > self.capacity {
+            if let Some(entry) = self.history.pop_front() {
+                self.history_bytes -= entry.len();
+            }
+        }
+
+        self.newest = Some(state);
+    }
+
+    /// Loads the frame before the newest one into `vm` and forgets the newest.
+    /// Returns false once there is no older frame left.
+    pub fn step_back<R: RandomSource>(&mut self, vm: &mut VirtualMachine<R>) -> bool {
+        let Some(entry) = self.history.pop_back() else {
+            return false;
+        };
+        let newest = self
+            .newest
+            .take()
+            .expect("history is only recorded behind a newest state");
+        self.history_bytes -= entry.len();
+
+        let previous = match entry {
+            Entry::Delta(delta) => apply_delta(newest, &delta),
+            Entry::Full(state) => state,
+        };
+        vm.load_state(&previous)
+            .expect("rewind history only holds states written by save_state");
+        self.newest = Some(previous);
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.history.clear();
+        self.history_bytes = 0;
+    }
+
+    /// Number of frames that can currently be stepped back through
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Bytes held by the recorded frames
+    pub fn memory_usage(&self) -> usize {
+        self.history_bytes + self.newest.as_ref().map_or(0, Vec::len)
+    }
+}
+
+// XORs `a` with `b` and encodes the result as pairs of runs: the number of
+// unchanged bytes, then a count of changed bytes followed by those XORed bytes.
+// Both counts are LEB128 varints.
+fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+
+    while position < a.len() {
+        let unchanged = a[position..]
+            .iter()
+            .zip(&b[position..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        position += unchanged;
+
+        let changed = a[position..]
+            .iter()
+            .zip(&b[position..])
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend(
+            a[position..position + changed]
+                .iter()
+                .zip(&b[position..])
+                .map(|(a, b)| a ^ b),
+        );
+        position += changed;
+    }
+
+    delta
+}
+
+fn apply_delta(mut state: Vec<u8>, delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let mut delta = delta;
+
+    while !delta.is_empty() {
+        position += read_varint(&mut delta);
+        let changed = read_varint(&mut delta);
+
+        for (byte, xor) in state[position..position + changed].iter_mut().zip(delta) {
+            *byte ^= xor;
+        }
+        delta = &delta[changed..];
+        position += changed;
+    }
+
+    state
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some((&byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
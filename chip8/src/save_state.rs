@@ -0,0 +1,393 @@
+//! Binary save states.
+//!
+//! Every state starts with a ten byte header, all integers are little endian:
+//!
+//! | offset | size | field                        |
+//! |--------|------|------------------------------|
+//! | 0      | 4    | magic, `C8SS`                |
+//! | 4      | 2    | format version               |
+//! | 6      | 4    | length of the payload        |
+//! | 10     | ..   | payload                      |
+//!
+//! Version 3 payload:
+//!
+//! | size            | field                                                  |
+//! |-----------------|--------------------------------------------------------|
+//! | 1               | instruction set: 0 CHIP-8, 1 SUPER-CHIP, 2 XO-CHIP     |
+//! | 6               | quirks: shift, load/store index (0 none, 1 X, 2 X + 1), jump, VF reset, clip, display wait |
+//! | 4096 or 65536   | memory, 64 KiB for XO-CHIP                             |
+//! | 16              | V0 to VF                                               |
+//! | 32              | stack, 16 `u16`s                                       |
+//! | 1               | stack pointer                                          |
+//! | 8192            | 128x64 screen, rows as wide as the current resolution  |
+//! | 1               | hi-res flag                                            |
+//! | 1               | selected planes                                        |
+//! | 16              | RPL flags                                              |
+//! | 16              | audio pattern                                          |
+//! | 1               | pitch                                                  |
+//! | 2               | I                                                      |
+//! | 2               | PC                                                     |
+//! | 1               | delay timer                                            |
+//! | 1               | sound timer                                            |
+//! | 16              | key state                                              |
+//! | 1               | blocked on key press                                   |
+//! | 1               | waiting for vblank                                     |
+//! | 1               | exited                                                 |
+//! | 8               | state of the random source                             |
+//! | 1               | stack depth, 1 to 16                                   |
+//! | 1               | font: 0 COSMAC VIP, 1 CHIP-48                          |
+//!
+//! Version 2 is the same without the stack depth and font, which load as 16
+//! and CHIP-48. Version 1 also lacks the random source, loading it leaves the
+//! VM's random source untouched.
+//!
+//! Booleans are stored as one byte, 0 or 1. States are written in the
+//! newest version; older versions are migrated as they are loaded.
+
+use core::fmt;
+
+use crate::{Font, IndexIncrement, InstructionSet, Quirks, RandomSource, VirtualMachine};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 3;
+const HEADER_LEN: usize = 10;
+const SCREEN_BYTES: usize = crate::HIRES_SCREEN_WIDTH * crate::HIRES_SCREEN_HEIGHT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::InvalidValue(field) => {
+                write!(f, "Save state has an invalid {}", field)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SaveStateError {}
+
+/// Length of the save state at the start of `data`, header included.
+/// Lets several states be stored back to back.
+pub fn state_len(data: &[u8]) -> Result<usize, SaveStateError> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(4)? != MAGIC {
+        return Err(SaveStateError::BadMagic);
+    }
+    reader.u16()?;
+
+    Ok(HEADER_LEN + reader.u32()? as usize)
+}
+
+impl<R: RandomSource> VirtualMachine<R> {
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        payload.push(match self.instruction_set {
+            InstructionSet::Chip8 => 0,
+            InstructionSet::SuperChip => 1,
+            InstructionSet::XoChip => 2,
+        });
+        let quirks = self.quirks;
+        payload.extend([
+            quirks.shift_uses_vy as u8,
+            match quirks.load_store_index {
+                IndexIncrement::None => 0,
+                IndexIncrement::X => 1,
+                IndexIncrement::XPlusOne => 2,
+            },
+            quirks.jump_uses_vx as u8,
+            quirks.vf_reset as u8,
+            quirks.clip_sprites as u8,
+            quirks.display_wait as u8,
+        ]);
+        payload.extend_from_slice(&self.memory[..self.memory_size()]);
+        payload.extend_from_slice(&self.registers);
+        for address in self.stack {
+            payload.extend(address.to_le_bytes());
+        }
+        payload.push(self.stack_pointer);
+        // A byte per pixel, as the screen was stored before it was bit-packed
+        let screen_start = payload.len();
+        payload.resize(screen_start + SCREEN_BYTES, 0);
+        let mut bytes = payload[screen_start..].iter_mut();
+        for row in self.screen_rows() {
+            for (&pixel, byte) in row.iter().zip(&mut bytes) {
+                *byte = pixel;
+            }
+        }
+        payload.push(self.high_resolution as u8);
+        payload.push(self.selected_planes);
+        payload.extend_from_slice(&self.rpl_flags);
+        payload.extend_from_slice(&self.audio_pattern);
+        payload.push(self.pitch);
+        payload.extend(self.index_register.to_le_bytes());
+        payload.extend(self.program_counter.to_le_bytes());
+        payload.push(self.delay_timer);
+        payload.push(self.sound_timer);
+        payload.extend(self.key_state.map(|pressed| pressed as u8));
+        payload.push(self.blocked_on_key_press as u8);
+        payload.push(self.waiting_for_vblank as u8);
+        payload.push(self.exited as u8);
+        payload.extend(self.rng.state().to_le_bytes());
+        payload.push(self.stack_depth);
+        payload.push(match self.font {
+            Font::CosmacVip => 0,
+            Font::Chip48 => 1,
+        });
+
+        let mut state = Vec::with_capacity(HEADER_LEN + payload.len());
+        state.extend_from_slice(MAGIC);
+        state.extend(SAVE_STATE_VERSION.to_le_bytes());
+        state.extend((payload.len() as u32).to_le_bytes());
+        state.extend(payload);
+        state
+    }
+
+    /// Restores a state written by `save_state`. On error the VM is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = Reader::new(data);
+        if reader.bytes(4)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        let payload_len = reader.u32()? as usize;
+        let mut reader = Reader::new(reader.bytes(payload_len)?);
+
+        let fields = match version {
+            1 => Fields::read_v1(&mut reader)?,
+            2 => Fields::read_v2(&mut reader)?,
+            3 => Fields::read_v3(&mut reader)?,
+            version => return Err(SaveStateError::UnsupportedVersion(version)),
+        };
+        fields.apply(self);
+
+        Ok(())
+    }
+}
+
+// Borrowed view of a save state, checked before any of it touches the VM
+struct Fields<'a> {
+    instruction_set: InstructionSet,
+    quirks: Quirks,
+    memory: &'a [u8],
+    registers: &'a [u8],
+    stack: [u16; 16],
+    stack_pointer: u8,
+    screen: &'a [u8],
+    high_resolution: bool,
+    selected_planes: u8,
+    rpl_flags: &'a [u8],
+    audio_pattern: &'a [u8],
+    pitch: u8,
+    index_register: u16,
+    program_counter: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    key_state: [bool; 16],
+    blocked_on_key_press: bool,
+    waiting_for_vblank: bool,
+    exited: bool,
+    /// Missing from version 1, which leaves the VM's generator as it is
+    rng_state: Option<u64>,
+    stack_depth: u8,
+    font: Font,
+}
+
+impl<'a> Fields<'a> {
+    fn read_v3(reader: &mut Reader<'a>) -> Result<Self, SaveStateError> {
+        let mut fields = Self::read_v2(reader)?;
+        fields.stack_depth = reader.u8()?;
+        if !(1..=fields.stack.len()).contains(&(fields.stack_depth as usize)) {
+            return Err(SaveStateError::InvalidValue("stack depth"));
+        }
+        fields.font = match reader.u8()? {
+            0 => Font::CosmacVip,
+            1 => Font::Chip48,
+            _ => return Err(SaveStateError::InvalidValue("font")),
+        };
+
+        Ok(fields)
+    }
+
+    fn read_v2(reader: &mut Reader<'a>) -> Result<Self, SaveStateError> {
+        let mut fields = Self::read_v1(reader)?;
+        fields.rng_state = Some(reader.u64()?);
+
+        Ok(fields)
+    }
+
+    fn read_v1(reader: &mut Reader<'a>) -> Result<Self, SaveStateError> {
+        let instruction_set = match reader.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            _ => return Err(SaveStateError::InvalidValue("instruction set")),
+        };
+        let quirks = Quirks {
+            shift_uses_vy: reader.bool()?,
+            load_store_index: match reader.u8()? {
+                0 => IndexIncrement::None,
+                1 => IndexIncrement::X,
+                2 => IndexIncrement::XPlusOne,
+                _ => return Err(SaveStateError::InvalidValue("load/store quirk")),
+            },
+            jump_uses_vx: reader.bool()?,
+            vf_reset: reader.bool()?,
+            clip_sprites: reader.bool()?,
+            display_wait: reader.bool()?,
+        };
+        let memory_size = match instruction_set {
+            InstructionSet::XoChip => crate::XO_CHIP_MEMORY_SIZE,
+            _ => crate::MEMORY_SIZE,
+        };
+        let memory = reader.bytes(memory_size)?;
+        let registers = reader.bytes(16)?;
+        let mut stack = [0; 16];
+        for address in &mut stack {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()?;
+        if stack_pointer as usize > stack.len() {
+            return Err(SaveStateError::InvalidValue("stack pointer"));
+        }
+
+        Ok(Fields {
+            instruction_set,
+            quirks,
+            memory,
+            registers,
+            stack,
+            stack_pointer,
+            screen: reader.bytes(SCREEN_BYTES)?,
+            high_resolution: reader.bool()?,
+            selected_planes: reader.u8()?,
+            rpl_flags: reader.bytes(16)?,
+            audio_pattern: reader.bytes(16)?,
+            pitch: reader.u8()?,
+            index_register: reader.u16()?,
+            program_counter: reader.u16()?,
+            delay_timer: reader.u8()?,
+            sound_timer: reader.u8()?,
+            key_state: {
+                let mut key_state = [false; 16];
+                for pressed in &mut key_state {
+                    *pressed = reader.bool()?;
+                }
+                key_state
+            },
+            blocked_on_key_press: reader.bool()?,
+            waiting_for_vblank: reader.bool()?,
+            exited: reader.bool()?,
+            rng_state: None,
+            stack_depth: 16,
+            font: Font::Chip48,
+        })
+    }
+
+    fn apply<R: RandomSource>(self, vm: &mut VirtualMachine<R>) {
+        vm.instruction_set = self.instruction_set;
+        vm.quirks = self.quirks;
+        vm.memory[..self.memory.len()].copy_from_slice(self.memory);
+        vm.memory[self.memory.len()..].fill(0);
+        vm.registers.copy_from_slice(self.registers);
+        vm.stack = self.stack;
+        vm.stack_pointer = self.stack_pointer;
+        vm.stack_depth = self.stack_depth;
+        vm.font = self.font;
+        vm.high_resolution = self.high_resolution;
+        vm.screen = [[0; crate::HIRES_SCREEN_HEIGHT]; crate::PLANE_COUNT];
+        let width = vm.screen_width();
+        for (row, pixels) in self
+            .screen
+            .chunks_exact(width)
+            .take(vm.screen_height())
+            .enumerate()
+        {
+            for (col, pixel) in pixels.iter().enumerate() {
+                for plane in 0..crate::PLANE_COUNT {
+                    if pixel & 1 << plane != 0 {
+                        vm.screen[plane][row] |= 1 << (crate::HIRES_SCREEN_WIDTH - 1 - col);
+                    }
+                }
+            }
+        }
+        vm.mark_screen_dirty();
+        vm.selected_planes = self.selected_planes;
+        vm.rpl_flags.copy_from_slice(self.rpl_flags);
+        vm.audio_pattern.copy_from_slice(self.audio_pattern);
+        vm.pitch = self.pitch;
+        vm.index_register = self.index_register;
+        vm.program_counter = self.program_counter;
+        vm.delay_timer = self.delay_timer;
+        vm.sound_timer = self.sound_timer;
+        vm.key_state = self.key_state;
+        vm.blocked_on_key_press = self.blocked_on_key_press;
+        vm.waiting_for_vblank = self.waiting_for_vblank;
+        vm.exited = self.exited;
+        if let Some(state) = self.rng_state {
+            vm.rng.set_state(state);
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("flag")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
@@ -1,38 +1,1784 @@
 use super::*;
 use proptest::prelude::*;
 
-const TOTAL_PIXELS: usize = SCREEN_HEIGHT * SCREEN_WIDTH;
+fn arb_index_increment() -> impl Strategy<Value = IndexIncrement> {
+    prop_oneof![
+        Just(IndexIncrement::None),
+        Just(IndexIncrement::X),
+        Just(IndexIncrement::XPlusOne),
+    ]
+}
+
+fn arb_instruction_set() -> impl Strategy<Value = InstructionSet> {
+    prop_oneof![
+        Just(InstructionSet::Chip8),
+        Just(InstructionSet::SuperChip),
+        Just(InstructionSet::XoChip),
+    ]
+}
+
+prop_compose! {
+    fn arb_quirks()(shift_uses_vy in any::<bool>(),
+    load_store_index in arb_index_increment(),
+    jump_uses_vx in any::<bool>(),
+    vf_reset in any::<bool>(),
+    clip_sprites in any::<bool>(),
+    display_wait in any::<bool>()) -> Quirks {
+        Quirks {
+            shift_uses_vy,
+            load_store_index,
+            jump_uses_vx,
+            vf_reset,
+            clip_sprites,
+            display_wait,
+        }
+    }
+}
 
 prop_compose! {
-    fn arb_vm()(memory in any::<[u8; 4096]>(),
+    // Only the first 4 KiB of memory is randomized to keep XO-CHIP cases cheap
+    fn arb_vm()(low_memory in proptest::collection::vec(any::<u8>(), MEMORY_SIZE),
     registers in any::<[u8; 16]>(),
     stack in any::<[u16; 16]>(),
-    stack_pointer in any::<u8>(),
-    screen in any::<[u8; TOTAL_PIXELS]>(),
+    stack_pointer in 0..=16_u8,
+    stack_depth in 1..=16_u8,
+    screen in proptest::collection::vec(any::<u128>(), HIRES_SCREEN_HEIGHT * PLANE_COUNT),
+    high_resolution in any::<bool>(),
+    selected_planes in 0..4_u8,
+    rpl_flags in any::<[u8; 16]>(),
+    audio_pattern in any::<[u8; 16]>(),
+    pitch in any::<u8>(),
     index_register in any::<u16>(), program_counter in any::<u16>(),
     delay_timer in any::<u8>(), sound_timer in any::<u8>(),
     key_state in any::<[bool; 16]>(),
-    blocked_on_key_press in any::<bool>()) -> VirtualMachine {
-        VirtualMachine {
+    blocked_on_key_press in any::<bool>(),
+    waiting_for_vblank in any::<bool>(),
+    exited in any::<bool>(),
+    instruction_set in arb_instruction_set(),
+    quirks in arb_quirks(),
+    font in prop_oneof![Just(Font::CosmacVip), Just(Font::Chip48)],
+    seed in any::<u64>()) -> Box<VirtualMachine> {
+        let mut memory = [0; XO_CHIP_MEMORY_SIZE];
+        memory[..MEMORY_SIZE].copy_from_slice(&low_memory);
+
+        let mut planes = [[0; HIRES_SCREEN_HEIGHT]; PLANE_COUNT];
+        for (row, bits) in planes.iter_mut().flatten().zip(screen) {
+            // Nothing is lit off the low resolution screen
+            *row = if high_resolution {
+                bits
+            } else {
+                bits & !0 << SCREEN_WIDTH
+            };
+        }
+        if !high_resolution {
+            for plane in &mut planes {
+                plane[SCREEN_HEIGHT..].fill(0);
+            }
+        }
+
+        // Boxed, as proptest moves values around more than the test stack can take
+        Box::new(VirtualMachine {
             memory,
             registers,
             stack,
             stack_pointer,
-            screen,
+            stack_depth,
+            screen: planes,
+            high_resolution,
+            // What a freshly loaded state reports
+            dirty: Some(DirtyRect {
+                x: 0,
+                y: 0,
+                width: if high_resolution { HIRES_SCREEN_WIDTH } else { SCREEN_WIDTH },
+                height: if high_resolution { HIRES_SCREEN_HEIGHT } else { SCREEN_HEIGHT },
+            }),
+            selected_planes,
+            rpl_flags,
+            audio_pattern,
+            pitch,
             index_register,
             program_counter,
             delay_timer,
             sound_timer,
             key_state,
-            blocked_on_key_press
-        }
+            blocked_on_key_press,
+            waiting_for_vblank,
+            exited,
+            instruction_set,
+            quirks,
+            font,
+            rng: SeededRandom::new(seed)
+        })
     }
 }
 
+fn vm_with_program(quirks: Quirks, program: &[u8]) -> VirtualMachine {
+    let mut vm = VirtualMachine::with_quirks(quirks);
+    vm.load_rom(program).unwrap();
+    vm
+}
+
+fn screen(vm: &VirtualMachine) -> Vec<Vec<u8>> {
+    vm.screen_rows().map(|row| row.to_vec()).collect()
+}
+
 proptest! {
     #[test]
     fn test_reset(mut vm in arb_vm()) {
         vm.reset();
-        assert_eq!(vm, VirtualMachine::new());
+
+        let mut expected = VirtualMachine::with_config(vm.instruction_set, vm.quirks);
+        expected.rpl_flags = vm.rpl_flags;
+        expected.stack_depth = vm.stack_depth;
+        expected.set_font(vm.font);
+        expected.rng = vm.rng.clone();
+        assert_eq!(*vm, expected);
+    }
+
+    #[test]
+    fn test_shift_quirk(vx in any::<u8>(), vy in any::<u8>(), shift_uses_vy in any::<bool>()) {
+        let quirks = Quirks { shift_uses_vy, ..Quirks::default() };
+        // 8126: V1 = V1 >> 1 (or V1 = V2 >> 1)
+        let mut vm = vm_with_program(quirks, &[0x81, 0x26]);
+        vm.registers[1] = vx;
+        vm.registers[2] = vy;
+        vm.execute_instruction().unwrap();
+
+        let source = if shift_uses_vy { vy } else { vx };
+        assert_eq!(vm.registers[1], source >> 1);
+        assert_eq!(vm.registers[0xF], source & 1);
+    }
+}
+
+#[test]
+fn test_load_store_index_quirk() {
+    for (load_store_index, expected) in [
+        (IndexIncrement::None, 0x300),
+        (IndexIncrement::X, 0x303),
+        (IndexIncrement::XPlusOne, 0x304),
+    ] {
+        let quirks = Quirks {
+            load_store_index,
+            ..Quirks::default()
+        };
+        // A300: I = 0x300, F355: store V0..=V3
+        let mut vm = vm_with_program(quirks, &[0xA3, 0x00, 0xF3, 0x55]);
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+
+        assert_eq!(vm.index_register, expected);
+    }
+}
+
+#[test]
+fn test_jump_quirk() {
+    for (jump_uses_vx, expected) in [(false, 0x310), (true, 0x320)] {
+        let quirks = Quirks {
+            jump_uses_vx,
+            ..Quirks::default()
+        };
+        // B300: jump to 0x300 + V0 (or 0x300 + V3)
+        let mut vm = vm_with_program(quirks, &[0xB3, 0x00]);
+        vm.registers[0] = 0x10;
+        vm.registers[3] = 0x20;
+        vm.execute_instruction().unwrap();
+
+        assert_eq!(vm.program_counter, expected);
+    }
+}
+
+#[test]
+fn test_sprite_clipping_quirk() {
+    for clip_sprites in [false, true] {
+        let quirks = Quirks {
+            clip_sprites,
+            ..Quirks::default()
+        };
+        // A206: I = sprite, D011: draw 1 row at (V0, V1), sprite byte 0xFF
+        let mut vm = vm_with_program(quirks, &[0xA2, 0x06, 0xD0, 0x11, 0x00, 0x00, 0xFF]);
+        vm.registers[0] = (SCREEN_WIDTH - 4) as u8;
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+
+        let row = screen(&vm).remove(0);
+        assert_eq!(row[SCREEN_WIDTH - 4..], [1, 1, 1, 1]);
+        assert_eq!(row[..4], if clip_sprites { [0; 4] } else { [1; 4] });
+    }
+}
+
+#[test]
+fn test_display_wait_quirk() {
+    let quirks = Quirks {
+        display_wait: true,
+        ..Quirks::default()
+    };
+    // D000: draw an empty sprite, 6005: V0 = 5
+    let mut vm = vm_with_program(quirks, &[0xD0, 0x00, 0x60, 0x05]);
+    vm.execute_instruction().unwrap();
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.registers[0], 0);
+
+    vm.decrement_timers();
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.registers[0], 5);
+}
+
+#[test]
+fn test_unknown_opcode() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x60, 0x01, 0xE0, 0x00]);
+    vm.execute_instruction().unwrap();
+
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::UnknownOpcode {
+            pc: 0x202,
+            opcode: 0xE000
+        })
+    );
+    assert_eq!(vm.program_counter, 0x202);
+}
+
+#[test]
+fn test_stack_faults() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x00, 0xEE]);
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::StackUnderflow {
+            pc: 0x200,
+            opcode: 0x00EE
+        })
+    );
+
+    // 2200: call itself until the stack runs out
+    let mut vm = vm_with_program(Quirks::default(), &[0x22, 0x00]);
+    for _ in 0..16 {
+        vm.execute_instruction().unwrap();
+    }
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::StackOverflow {
+            pc: 0x200,
+            opcode: 0x2200
+        })
+    );
+}
+
+#[test]
+fn test_memory_out_of_bounds() {
+    // AFFE: I = 0xFFE, F355: store V0..=V3
+    let mut vm = vm_with_program(Quirks::default(), &[0xAF, 0xFE, 0xF3, 0x55]);
+    vm.execute_instruction().unwrap();
+
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::MemoryOutOfBounds {
+            pc: 0x202,
+            opcode: 0xF355,
+            address: 0x1000
+        })
+    );
+    assert_eq!(vm.index_register, 0xFFE);
+}
+
+#[test]
+fn test_pc_out_of_bounds() {
+    // 1FFF: jump to the last byte of memory
+    let mut vm = vm_with_program(Quirks::default(), &[0x1F, 0xFF]);
+
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::PcOutOfBounds {
+            pc: 0x200,
+            opcode: 0x1FFF
+        })
+    );
+}
+
+#[test]
+fn test_load_rom_too_large() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x12, 0x00]);
+    let before = copy_vm(&vm);
+
+    let rom = vec![0xAA; MEMORY_SIZE - 0x200 + 1];
+    assert_eq!(
+        vm.load_rom(&rom),
+        Err(LoadError::TooLarge {
+            address: 0x200,
+            len: MEMORY_SIZE - 0x200 + 1,
+            available: MEMORY_SIZE - 0x200
+        })
+    );
+    assert_eq!(vm, *before);
+
+    vm.load_rom(&rom[1..]).unwrap();
+    assert_eq!(vm.memory[MEMORY_SIZE - 1], 0xAA);
+
+    // XO-CHIP has 64 KiB to fill
+    let mut vm = xo_chip_vm(&[]);
+    vm.load_rom(&rom).unwrap();
+}
+
+#[test]
+fn test_load_options() {
+    let mut vm = VirtualMachine::new();
+    vm.load_rom_with(&[0x12, 0x34], LoadOptions::ETI_660)
+        .unwrap();
+    assert_eq!(vm.program_counter, 0x600);
+    assert_eq!(vm.memory[0x600..0x602], [0x12, 0x34]);
+    assert_eq!(vm.memory[0x200..0x202], [0, 0]);
+
+    let options = LoadOptions {
+        load_address: 0x200,
+        entry_point: MEMORY_SIZE as u16 - 1,
+    };
+    assert_eq!(
+        vm.load_rom_with(&[], options),
+        Err(LoadError::EntryPointOutOfBounds(MEMORY_SIZE as u16 - 1))
+    );
+    assert_eq!(vm.program_counter, 0x600);
+}
+
+#[test]
+fn test_load_segments() {
+    let mut vm = VirtualMachine::new();
+    vm.load_segments(
+        &[(0x300, &[1, 2, 3]), (0x200, &[4, 5]), (0x301, &[6])],
+        0x202,
+    )
+    .unwrap();
+    assert_eq!(vm.program_counter, 0x202);
+    assert_eq!(vm.memory[0x200..0x202], [4, 5]);
+    assert_eq!(vm.memory[0x300..0x303], [1, 6, 3]);
+
+    assert_eq!(
+        vm.load_segments(&[(0x200, &[7]), (0xFFF, &[8, 9])], 0x200),
+        Err(LoadError::TooLarge {
+            address: 0xFFF,
+            len: 2,
+            available: 1
+        })
+    );
+    assert_eq!(vm.memory[0x200], 4);
+}
+
+proptest! {
+    #[test]
+    fn test_execute_never_panics(mut vm in arb_vm()) {
+        for _ in 0..64 {
+            if vm.execute_instruction().is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// The 35 CHIP-8 opcodes, each as a pattern and the bits that vary
+const CHIP8_OPCODES: [(u16, u16); 35] = [
+    (0x00E0, 0x0000),
+    (0x00EE, 0x0000),
+    (0x0000, 0x0FFF),
+    (0x1000, 0x0FFF),
+    (0x2000, 0x0FFF),
+    (0x3000, 0x0FFF),
+    (0x4000, 0x0FFF),
+    (0x5000, 0x0FF0),
+    (0x6000, 0x0FFF),
+    (0x7000, 0x0FFF),
+    (0x8000, 0x0FF0),
+    (0x8001, 0x0FF0),
+    (0x8002, 0x0FF0),
+    (0x8003, 0x0FF0),
+    (0x8004, 0x0FF0),
+    (0x8005, 0x0FF0),
+    (0x8006, 0x0FF0),
+    (0x8007, 0x0FF0),
+    (0x800E, 0x0FF0),
+    (0x9000, 0x0FF0),
+    (0xA000, 0x0FFF),
+    (0xB000, 0x0FFF),
+    (0xC000, 0x0FFF),
+    (0xD000, 0x0FFF),
+    (0xE09E, 0x0F00),
+    (0xE0A1, 0x0F00),
+    (0xF007, 0x0F00),
+    (0xF00A, 0x0F00),
+    (0xF015, 0x0F00),
+    (0xF018, 0x0F00),
+    (0xF01E, 0x0F00),
+    (0xF029, 0x0F00),
+    (0xF033, 0x0F00),
+    (0xF055, 0x0F00),
+    (0xF065, 0x0F00),
+];
+
+fn arb_chip8_opcode() -> impl Strategy<Value = u16> {
+    (0..CHIP8_OPCODES.len(), any::<u16>()).prop_map(|(index, bits)| {
+        let (pattern, mask) = CHIP8_OPCODES[index];
+        pattern | bits & mask
+    })
+}
+
+fn copy_vm(vm: &VirtualMachine) -> Box<VirtualMachine> {
+    Box::new(VirtualMachine {
+        rng: vm.rng.clone(),
+        ..*vm
+    })
+}
+
+// What the opcode at PC does to a low-resolution CHIP-8 machine drawing on
+// the first plane, written out independently of `execute`
+fn reference_step(vm: &VirtualMachine) -> Result<Box<VirtualMachine>, VmError> {
+    let pc = vm.program_counter;
+    let opcode = (vm.memory[pc as usize] as u16) << 8 | vm.memory[pc as usize + 1] as u16;
+    let x = (opcode >> 8 & 0xF) as usize;
+    let y = (opcode >> 4 & 0xF) as usize;
+    let n = (opcode & 0xF) as usize;
+    let nn = (opcode & 0xFF) as u8;
+    let nnn = opcode & 0xFFF;
+    let (vx, vy) = (vm.registers[x], vm.registers[y]);
+    let index = vm.index_register as usize;
+    let memory_check = |len: usize| {
+        if index + len > MEMORY_SIZE {
+            Err(VmError::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address: index.max(MEMORY_SIZE),
+            })
+        } else {
+            Ok(())
+        }
+    };
+
+    let mut next = copy_vm(vm);
+    let mut next_pc = pc.wrapping_add(2);
+    let skip = |condition: bool| {
+        if condition {
+            pc.wrapping_add(4)
+        } else {
+            pc.wrapping_add(2)
+        }
+    };
+    let shift_source = if vm.quirks.shift_uses_vy { vy } else { vx };
+
+    match (opcode >> 12, n) {
+        _ if opcode == 0x00E0 => next.screen[0] = [0; HIRES_SCREEN_HEIGHT],
+        _ if opcode == 0x00EE => {
+            let sp = vm.stack_pointer as usize;
+            if sp == 0 {
+                return Err(VmError::StackUnderflow { pc, opcode });
+            }
+            if sp > 16 {
+                return Err(VmError::StackOverflow { pc, opcode });
+            }
+            next_pc = vm.stack[sp - 1].wrapping_add(2);
+            next.stack_pointer -= 1;
+        }
+        (0x0, _) => return Err(VmError::UnknownOpcode { pc, opcode }),
+        (0x1, _) => next_pc = nnn,
+        (0x2, _) => {
+            let sp = vm.stack_pointer as usize;
+            if sp >= vm.stack_depth as usize {
+                return Err(VmError::StackOverflow { pc, opcode });
+            }
+            next.stack[sp] = pc;
+            next.stack_pointer += 1;
+            next_pc = nnn;
+        }
+        (0x3, _) => next_pc = skip(vx == nn),
+        (0x4, _) => next_pc = skip(vx != nn),
+        (0x5, _) => next_pc = skip(vx == vy),
+        (0x6, _) => next.registers[x] = nn,
+        (0x7, _) => next.registers[x] = vx.wrapping_add(nn),
+        (0x8, 0x0) => next.registers[x] = vy,
+        (0x8, 0x1..=0x3) => {
+            next.registers[x] = match n {
+                0x1 => vx | vy,
+                0x2 => vx & vy,
+                _ => vx ^ vy,
+            };
+            if vm.quirks.vf_reset {
+                next.registers[0xF] = 0;
+            }
+        }
+        // The flag is written after the result, so it wins when X is F
+        (0x8, 0x4) => {
+            next.registers[x] = vx.wrapping_add(vy);
+            next.registers[0xF] = (vx as u16 + vy as u16 > 0xFF) as u8;
+        }
+        // VF is 1 when there is no borrow, including when both are equal
+        (0x8, 0x5) => {
+            next.registers[x] = vx.wrapping_sub(vy);
+            next.registers[0xF] = (vx >= vy) as u8;
+        }
+        (0x8, 0x6) => {
+            next.registers[x] = shift_source >> 1;
+            next.registers[0xF] = shift_source & 1;
+        }
+        (0x8, 0x7) => {
+            next.registers[x] = vy.wrapping_sub(vx);
+            next.registers[0xF] = (vy >= vx) as u8;
+        }
+        (0x8, 0xE) => {
+            next.registers[x] = shift_source << 1;
+            next.registers[0xF] = shift_source >> 7;
+        }
+        (0x9, _) => next_pc = skip(vx != vy),
+        (0xA, _) => next.index_register = nnn,
+        (0xB, _) => {
+            let offset = if vm.quirks.jump_uses_vx {
+                vx
+            } else {
+                vm.registers[0]
+            };
+            next_pc = nnn + offset as u16;
+        }
+        (0xC, _) => next.registers[x] = next.rng.next_byte() & nn,
+        (0xD, _) => {
+            memory_check(n)?;
+            next.registers[0xF] = 0;
+            let (origin_row, origin_col) =
+                (vy as usize % SCREEN_HEIGHT, vx as usize % SCREEN_WIDTH);
+            for (row, &bits) in vm.memory[index..index + n].iter().enumerate() {
+                let row = origin_row + row;
+                if row >= SCREEN_HEIGHT && vm.quirks.clip_sprites {
+                    break;
+                }
+                for col in 0..8 {
+                    let col = origin_col + col;
+                    if col >= SCREEN_WIDTH && vm.quirks.clip_sprites {
+                        break;
+                    }
+                    if bits & 0x80 >> (col - origin_col) == 0 {
+                        continue;
+                    }
+                    let bit = 1 << (HIRES_SCREEN_WIDTH - 1 - col % SCREEN_WIDTH);
+                    let screen_row = &mut next.screen[0][row % SCREEN_HEIGHT];
+                    if *screen_row & bit != 0 {
+                        next.registers[0xF] = 1;
+                    }
+                    *screen_row ^= bit;
+                }
+            }
+            next.waiting_for_vblank = vm.quirks.display_wait;
+        }
+        (0xE, _) if nn == 0x9E => next_pc = skip(vm.key_state[vx as usize & 0xF]),
+        (0xE, _) => next_pc = skip(!vm.key_state[vx as usize & 0xF]),
+        (0xF, _) => match nn {
+            0x07 => next.registers[x] = vm.delay_timer,
+            0x0A => {
+                next.blocked_on_key_press = true;
+                next.key_state = [false; 16];
+                next_pc = pc;
+            }
+            0x15 => next.delay_timer = vx,
+            0x18 => next.sound_timer = vx,
+            0x1E => next.index_register = vm.index_register.wrapping_add(vx as u16),
+            0x29 => next.index_register = (vx as u16 & 0xF) * 5,
+            0x33 => {
+                memory_check(3)?;
+                next.memory[index..index + 3].copy_from_slice(&[vx / 100, vx / 10 % 10, vx % 10]);
+            }
+            _ => {
+                memory_check(x + 1)?;
+                if nn == 0x55 {
+                    next.memory[index..=index + x].copy_from_slice(&vm.registers[..=x]);
+                } else {
+                    next.registers[..=x].copy_from_slice(&vm.memory[index..=index + x]);
+                }
+                next.index_register =
+                    vm.index_register
+                        .wrapping_add(match vm.quirks.load_store_index {
+                            IndexIncrement::None => 0,
+                            IndexIncrement::X => x as u16,
+                            IndexIncrement::XPlusOne => x as u16 + 1,
+                        });
+            }
+        },
+        _ => unreachable!("{:04X} is not a CHIP-8 opcode", opcode),
+    }
+
+    if next_pc as usize + 1 >= MEMORY_SIZE {
+        return Err(VmError::PcOutOfBounds { pc, opcode });
+    }
+    next.program_counter = next_pc;
+    Ok(next)
+}
+
+proptest! {
+    #[test]
+    fn test_opcode_semantics(
+        mut vm in arb_vm(),
+        opcode in arb_chip8_opcode(),
+        pc in PROGAM_COUNTER_START..MEMORY_SIZE as u16 - 1,
+    ) {
+        vm.instruction_set = InstructionSet::Chip8;
+        vm.high_resolution = false;
+        vm.selected_planes = 1;
+        vm.blocked_on_key_press = false;
+        vm.waiting_for_vblank = false;
+        vm.exited = false;
+        vm.program_counter = pc;
+        vm.memory[pc as usize..pc as usize + 2].copy_from_slice(&opcode.to_be_bytes());
+
+        let expected = reference_step(&vm);
+        let outcome = vm.execute_instruction();
+        match expected {
+            Ok(expected) => {
+                prop_assert_eq!(outcome, Ok(StepOutcome::Executed), "{:04X}", opcode);
+                // Field by field first, the whole machine is too big to read in a failure
+                prop_assert_eq!(vm.registers, expected.registers, "{:04X}", opcode);
+                prop_assert_eq!(vm.program_counter, expected.program_counter, "{:04X}", opcode);
+                prop_assert_eq!(vm.index_register, expected.index_register, "{:04X}", opcode);
+                prop_assert_eq!(vm.stack_pointer, expected.stack_pointer, "{:04X}", opcode);
+                prop_assert_eq!(vm.stack, expected.stack, "{:04X}", opcode);
+                prop_assert!(vm.memory == expected.memory, "{:04X} memory", opcode);
+                prop_assert!(vm.screen == expected.screen, "{:04X} screen", opcode);
+                prop_assert_eq!(vm, expected);
+            }
+            Err(error) => prop_assert_eq!(outcome, Err(error), "{:04X}", opcode),
+        }
+    }
+}
+
+fn superchip_vm(program: &[u8]) -> VirtualMachine {
+    let mut vm = VirtualMachine::with_config(InstructionSet::SuperChip, Quirks::SUPER_CHIP);
+    vm.load_rom(program).unwrap();
+    vm
+}
+
+#[test]
+fn test_superchip_opcodes_need_superchip() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x00, 0xFF]);
+
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::UnknownOpcode {
+            pc: 0x200,
+            opcode: 0x00FF
+        })
+    );
+}
+
+#[test]
+fn test_high_resolution_sprites() {
+    // 00FF: hi-res, F030: I = big "8" (V0 = 8), D11A: draw it at (V1, V1) = (0, 0),
+    // 6220 + A20E + D230: draw a solid 16x16 sprite at (32, 0)
+    let mut program = vec![
+        0x00, 0xFF, 0xF0, 0x30, 0xD1, 0x1A, 0x62, 0x20, 0xA2, 0x0E, 0xD2, 0x30, 0x00, 0x00,
+    ];
+    program.extend([0xFF; 32]);
+    let mut vm = superchip_vm(&program);
+    vm.registers[0] = 8;
+    for _ in 0..6 {
+        vm.execute_instruction().unwrap();
+    }
+
+    assert_eq!(vm.screen_width(), HIRES_SCREEN_WIDTH);
+    assert_eq!(vm.screen_height(), HIRES_SCREEN_HEIGHT);
+    assert_eq!(vm.screen_rows().count(), HIRES_SCREEN_HEIGHT);
+
+    let rows = screen(&vm);
+    assert_eq!(rows[0].len(), HIRES_SCREEN_WIDTH);
+    let row = vm.screen_rows().nth(2).unwrap();
+    assert_eq!(row.len(), HIRES_SCREEN_WIDTH);
+    assert_eq!(row[1..3], [1, 0]);
+    assert_eq!(vm.pixel(2, 2), 0);
+    assert_eq!(rows[0][..9], [1, 1, 1, 1, 1, 1, 1, 1, 0]);
+    assert_eq!(rows[2][..9], [1, 1, 0, 0, 0, 0, 1, 1, 0]);
+    assert!(rows[..16].iter().all(|row| row[32..48] == [1; 16]));
+    assert_eq!((rows[0][48], rows[16][32]), (0, 0));
+}
+
+#[test]
+fn test_scrolling() {
+    // D011: plot (0, 0) from the font's "0" row, 00C2: down 2, 00FB: right 4, 00FC: left 4
+    let mut vm = superchip_vm(&[0xD0, 0x11, 0x00, 0xC2, 0x00, 0xFB]);
+    vm.execute_instruction().unwrap();
+    vm.execute_instruction().unwrap();
+    let rows = screen(&vm);
+    assert_eq!(rows[0][..4], [0, 0, 0, 0]);
+    assert_eq!(rows[2][..4], [1, 1, 1, 1]);
+
+    vm.execute_instruction().unwrap();
+    let rows = screen(&vm);
+    assert_eq!(rows[2][..8], [0, 0, 0, 0, 1, 1, 1, 1]);
+
+    vm.scroll_left(4);
+    let rows = screen(&vm);
+    assert_eq!(rows[2][..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_take_frame() {
+    // V0 = 5, V1 = 3, I = font "0", draw it, clear twice, then go hi-res
+    let mut vm = superchip_vm(&[
+        0x60, 0x05, 0x61, 0x03, 0xA0, 0x00, 0xD0, 0x15, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0xFF,
+    ]);
+    let lores = DirtyRect {
+        x: 0,
+        y: 0,
+        width: SCREEN_WIDTH,
+        height: SCREEN_HEIGHT,
+    };
+    assert_eq!(vm.take_frame(), Some(lores));
+    assert_eq!(vm.take_frame(), None);
+
+    for _ in 0..3 {
+        vm.execute_instruction().unwrap();
+    }
+    assert_eq!(vm.take_frame(), None);
+
+    let glyph = DirtyRect {
+        x: 5,
+        y: 3,
+        width: 4,
+        height: 5,
+    };
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.take_frame(), Some(glyph));
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.take_frame(), Some(glyph));
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.take_frame(), None);
+
+    vm.execute_instruction().unwrap();
+    assert_eq!(
+        vm.take_frame(),
+        Some(DirtyRect {
+            x: 0,
+            y: 0,
+            width: HIRES_SCREEN_WIDTH,
+            height: HIRES_SCREEN_HEIGHT,
+        })
+    );
+
+    vm.load_state(&vm.save_state()).unwrap();
+    assert_eq!(
+        vm.take_frame().map(|rect| rect.width),
+        Some(HIRES_SCREEN_WIDTH)
+    );
+}
+
+#[test]
+fn test_rpl_flags_and_exit() {
+    // F275: save V0..=V2, 6000 + 6100: clear V0 and V1, F185: restore V0..=V1, 00FD: exit
+    let mut vm = superchip_vm(&[0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85, 0x00, 0xFD]);
+    vm.registers[..3].copy_from_slice(&[7, 8, 9]);
+    for _ in 0..5 {
+        vm.execute_instruction().unwrap();
+    }
+
+    assert_eq!(vm.registers[..3], [7, 8, 9]);
+    assert_eq!(vm.execute_instruction(), Ok(StepOutcome::Exited));
+    assert_eq!(vm.program_counter, 0x208);
+}
+
+fn xo_chip_vm(program: &[u8]) -> VirtualMachine {
+    let mut vm = VirtualMachine::with_config(InstructionSet::XoChip, Quirks::SUPER_CHIP);
+    vm.load_rom(program).unwrap();
+    vm
+}
+
+#[test]
+fn test_high_resolution_sprite_wrapping() {
+    // Hi-res, plane 2, V0 = 120, V1 = 62, I = 0x20C, then a 16x16 sprite twice
+    let mut program = vec![
+        0x00, 0xFF, 0xF2, 0x01, 0x60, 0x78, 0x61, 0x3E, 0xA2, 0x0C, 0xD0, 0x10,
+    ];
+    program.extend([0xFF; 32]);
+    let mut vm = xo_chip_vm(&program);
+    vm.set_quirks(Quirks {
+        clip_sprites: false,
+        ..Quirks::SUPER_CHIP
+    });
+    for _ in 0..6 {
+        vm.execute_instruction().unwrap();
+    }
+
+    let rows = screen(&vm);
+    for row in [62, 63, 0, 13] {
+        assert_eq!(rows[row][120..], [2; 8]);
+        assert_eq!(rows[row][..9], [2, 2, 2, 2, 2, 2, 2, 2, 0]);
+        assert_eq!(rows[row][119], 0);
+    }
+    assert!(rows[14..62].iter().flatten().all(|&pixel| pixel == 0));
+    assert!(vm.plane_rows(0).flatten().all(|lit| !lit));
+    assert_eq!(vm.registers[0xF], 0);
+
+    vm.take_frame();
+    vm.set_pc(0x20A);
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.registers[0xF], 1);
+    assert!(vm.screen_rows().flatten().all(|pixel| pixel == 0));
+    assert_eq!(
+        vm.take_frame(),
+        Some(DirtyRect {
+            x: 0,
+            y: 0,
+            width: HIRES_SCREEN_WIDTH,
+            height: HIRES_SCREEN_HEIGHT,
+        })
+    );
+}
+
+#[test]
+fn test_long_index_load_and_skip() {
+    // 3000: skip when V0 == 0, which must hop over the whole F000 1234
+    let mut vm = xo_chip_vm(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00, 0xAB, 0xCD]);
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.program_counter, 0x206);
+
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.index_register, 0xABCD);
+    assert_eq!(vm.program_counter, 0x20A);
+    assert_eq!(vm.memory_size(), 0x10000);
+}
+
+#[test]
+fn test_bitplanes() {
+    // F301: select both planes, A20A: sprite data, D011: draw one row per plane,
+    // F201: select plane 2, 00E0: clear plane 2 only
+    let mut vm = xo_chip_vm(&[
+        0xF3, 0x01, 0xA2, 0x0A, 0xD0, 0x11, 0xF2, 0x01, 0x00, 0xE0, 0xFF, 0x0F,
+    ]);
+    for _ in 0..3 {
+        vm.execute_instruction().unwrap();
+    }
+
+    let row = screen(&vm).remove(0);
+    assert_eq!(row[..8], [1, 1, 1, 1, 3, 3, 3, 3]);
+    let plane_2: Vec<bool> = vm.plane_rows(1).next().unwrap().collect();
+    assert_eq!(
+        plane_2[..8],
+        [false, false, false, false, true, true, true, true]
+    );
+    assert_eq!(vm.registers[0xF], 0);
+
+    vm.execute_instruction().unwrap();
+    vm.execute_instruction().unwrap();
+    let row = screen(&vm).remove(0);
+    assert_eq!(row[..8], [1, 1, 1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn test_register_range_save_load() {
+    // A300: I = 0x300, 5132: save V3..=V1 backwards, 6100..6300: clear, 5133: load them back
+    let mut vm = xo_chip_vm(&[
+        0xA3, 0x00, 0x53, 0x12, 0x61, 0x00, 0x62, 0x00, 0x63, 0x00, 0x51, 0x33,
+    ]);
+    vm.registers[1..4].copy_from_slice(&[1, 2, 3]);
+    for _ in 0..2 {
+        vm.execute_instruction().unwrap();
+    }
+    assert_eq!(vm.memory[0x300..0x303], [3, 2, 1]);
+    assert_eq!(vm.index_register, 0x300);
+
+    for _ in 0..4 {
+        vm.execute_instruction().unwrap();
+    }
+    assert_eq!(vm.registers[1..4], [3, 2, 1]);
+}
+
+#[test]
+fn test_audio_pattern_and_pitch() {
+    // A206: I = pattern, F002: load it, F03A: pitch = V0
+    let mut program = vec![0xA2, 0x06, 0xF0, 0x02, 0xF0, 0x3A];
+    program.extend(0..16);
+    let mut vm = xo_chip_vm(&program);
+    vm.registers[0] = 112;
+    for _ in 0..3 {
+        vm.execute_instruction().unwrap();
+    }
+
+    assert_eq!(vm.audio_pattern(), &core::array::from_fn(|i| i as u8));
+    assert_eq!(vm.pitch(), 112);
+    assert_eq!(vm.playback_rate(), 8000.0);
+
+    for pitch in 0..=255 {
+        vm.pitch = pitch;
+        let expected = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        assert!((vm.playback_rate() / expected - 1.0).abs() < 1e-5);
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_save_state_round_trip(vm in arb_vm()) {
+        let state = vm.save_state();
+        assert_eq!(state_len(&state), Ok(state.len()));
+
+        let mut loaded = VirtualMachine::new();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded, *vm);
+    }
+}
+
+#[test]
+fn test_load_state_rejects_bad_input() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x60, 0x01]);
+    vm.execute_instruction().unwrap();
+    let state = vm.save_state();
+    let mut other = VirtualMachine::new();
+
+    assert_eq!(
+        other.load_state(&state[..state.len() - 1]),
+        Err(SaveStateError::Truncated)
+    );
+    assert_eq!(other.load_state(b"NOPE"), Err(SaveStateError::BadMagic));
+
+    let mut future = state.clone();
+    future[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        other.load_state(&future),
+        Err(SaveStateError::UnsupportedVersion(SAVE_STATE_VERSION + 1))
+    );
+    assert_eq!(other, VirtualMachine::new());
+
+    other.load_state(&state).unwrap();
+    assert_eq!(other, vm);
+}
+
+// Cases the fuzz targets in chip8/fuzz turned up
+#[test]
+fn test_fuzz_regressions() {
+    // A save state claiming more calls than the stack holds made `stack` slice past its end
+    let mut state = VirtualMachine::new().save_state();
+    // Header, instruction set, quirks, memory, registers and stack come first
+    let stack_pointer_offset = 10 + 1 + 6 + MEMORY_SIZE + 16 + 2 * 16;
+    state[stack_pointer_offset] = 17;
+    let mut vm = VirtualMachine::new();
+    assert_eq!(
+        vm.load_state(&state),
+        Err(SaveStateError::InvalidValue("stack pointer"))
+    );
+    assert_eq!(vm.stack(), &[]);
+
+    state[stack_pointer_offset] = 16;
+    vm.load_state(&state).unwrap();
+    assert_eq!(vm.stack(), &[0; 16]);
+}
+
+#[test]
+fn test_rewind() {
+    // 7001: V0 += 1, D011: draw, 1200: loop
+    let mut vm = vm_with_program(Quirks::default(), &[0x70, 0x01, 0xD0, 0x11, 0x12, 0x00]);
+    let mut rewind = Rewind::new(100);
+    let mut frames = Vec::new();
+
+    for _ in 0..150 {
+        for _ in 0..3 {
+            vm.execute_instruction().unwrap();
+        }
+        vm.decrement_timers();
+        rewind.push(&vm);
+        frames.push(vm.save_state());
+    }
+    assert_eq!(rewind.len(), 100);
+    assert!(rewind.memory_usage() < frames[0].len() + 100 * 64);
+
+    for expected in frames.iter().rev().skip(1).take(100) {
+        assert!(rewind.step_back(&mut vm));
+        assert_eq!(&vm.save_state(), expected);
     }
+    assert!(!rewind.step_back(&mut vm));
+    assert_eq!(&vm.save_state(), &frames[49]);
+}
+
+#[test]
+fn test_seeded_random_is_reproducible() {
+    // C0FF: V0 = random byte, 1200: loop
+    let run = |seed| {
+        let mut vm = VirtualMachine::with_random_source(
+            InstructionSet::Chip8,
+            Quirks::default(),
+            SeededRandom::new(seed),
+        );
+        vm.load_rom(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+        (0..32)
+            .map(|_| {
+                vm.execute_instruction().unwrap();
+                vm.execute_instruction().unwrap();
+                vm.registers[0]
+            })
+            .collect::<Vec<u8>>()
+    };
+
+    assert_eq!(run(1), run(1));
+    assert_ne!(run(1), run(2));
+}
+
+#[test]
+fn test_save_state_captures_random_source() {
+    let mut vm = vm_with_program(Quirks::default(), &[0xC0, 0xFF, 0x12, 0x00]);
+    let state = vm.save_state();
+    vm.execute_instruction().unwrap();
+    let first = vm.registers[0];
+
+    let mut other = VirtualMachine::with_random_source(
+        InstructionSet::Chip8,
+        Quirks::default(),
+        SeededRandom::new(!DEFAULT_SEED),
+    );
+    other.load_state(&state).unwrap();
+    other.execute_instruction().unwrap();
+    assert_eq!(other.registers[0], first);
+}
+
+#[test]
+fn test_load_version_1_state() {
+    let mut vm = vm_with_program(Quirks::default(), &[0x60, 0x2A]);
+    vm.execute_instruction().unwrap();
+
+    // Version 2 lacks the trailing stack depth and font, version 1 also the random source
+    let older_state = |version: u16, missing: usize| {
+        let mut state = vm.save_state();
+        state.truncate(state.len() - missing);
+        state[4..6].copy_from_slice(&version.to_le_bytes());
+        let payload_len = (state.len() - 10) as u32;
+        state[6..10].copy_from_slice(&payload_len.to_le_bytes());
+        state
+    };
+
+    let mut other = VirtualMachine::with_random_source(
+        InstructionSet::Chip8,
+        Quirks::default(),
+        SeededRandom::new(7),
+    );
+    other.set_platform(Platform::CosmacVip);
+    other.load_state(&older_state(1, 10)).unwrap();
+    assert_eq!(other.registers[0], 0x2A);
+    assert_eq!(other.program_counter, 0x202);
+    assert_eq!(other.random_source(), &SeededRandom::new(7));
+    assert_eq!((other.stack_depth(), other.font()), (16, Font::Chip48));
+
+    other.set_platform(Platform::CosmacVip);
+    other.load_state(&older_state(2, 2)).unwrap();
+    assert_eq!(other, vm);
+}
+
+#[test]
+fn test_cosmac_vip_random() {
+    let mut code_page = [0; 256];
+    code_page[1] = 0x10;
+    code_page[2] = 0x20;
+    let mut rng = CosmacVipRandom::new(code_page);
+
+    assert_eq!(rng.next_byte(), 0x10);
+    assert_eq!(rng.next_byte(), 0x30);
+    assert_eq!(rng.state(), 0x3002);
+
+    // The interrupt moves R9 on between CXNN, so the frame a byte is drawn on matters
+    let mut vm = VirtualMachine::with_random_source(
+        InstructionSet::Chip8,
+        Quirks::COSMAC_VIP,
+        CosmacVipRandom::new(code_page),
+    );
+    vm.decrement_timers();
+    assert_eq!(vm.random_source().state(), 0x0001);
+    assert_eq!(vm.random_source_mut().next_byte(), 0x20);
+}
+
+proptest! {
+    #[test]
+    fn test_decode_encode_round_trip(opcode in any::<u16>()) {
+        if let Ok(instruction) = decode(opcode) {
+            prop_assert_eq!(instruction.encode(), opcode);
+        }
+    }
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(decode(0x00E0), Ok(Instruction::Cls));
+    assert_eq!(decode(0x1234), Ok(Instruction::Jp(0x234)));
+    assert_eq!(decode(0xD12F), Ok(Instruction::Drw { x: 1, y: 2, n: 0xF }));
+    assert_eq!(decode(0xF53A), Ok(Instruction::Pitch { x: 5 }));
+    assert_eq!(Instruction::LdILong.size(), 4);
+    assert_eq!(
+        Instruction::LdRVx { x: 3 }.instruction_set(),
+        InstructionSet::SuperChip
+    );
+
+    for opcode in [0x0123, 0x5121, 0x8008, 0x9001, 0xE09F, 0xF017, 0xF0FF] {
+        assert_eq!(decode(opcode), Err(DecodeError { opcode }));
+    }
+}
+
+#[test]
+fn test_xo_chip_opcodes_need_xo_chip() {
+    // Used to run as FX0A before XO-CHIP, when only the low nibble was checked
+    let mut vm = superchip_vm(&[0xF5, 0x3A]);
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::UnknownOpcode {
+            pc: 0x200,
+            opcode: 0xF53A
+        })
+    );
+    assert!(!vm.blocked_on_key_press);
+}
+
+// Calls a subroutine that draws a sprite stored after the code, then spins
+const DISASM_ROM: &[u8] = &[
+    0x22, 0x06, // 200: CALL 0x206
+    0x30, 0x01, // 202: SE V0, 0x01
+    0x12, 0x04, // 204: JP 0x204
+    0xA2, 0x0C, // 206: LD I, 0x20C
+    0xD0, 0x12, // 208: DRW V0, V1, 2
+    0x00, 0xEE, // 20A: RET
+    0x3C, 0x42, // 20C: sprite
+];
+
+#[test]
+fn test_find_code() {
+    let code = disasm::find_code(DISASM_ROM);
+    let starts: Vec<usize> = (0..code.len()).filter(|&offset| code[offset]).collect();
+    assert_eq!(starts, [0, 2, 4, 6, 8, 10]);
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(
+        disasm::disassemble(DISASM_ROM, disasm::Syntax::Classic),
+        "\
+200: 2206             CALL 0x206
+202: 3001             SE V0, 0x01
+label_204:
+204: 1204             JP 0x204
+label_206:
+206: A20C             LD I, 0x20C
+208: D012             DRW V0, V1, 2
+20A: 00EE             RET
+label_20C:
+20C: 3C42             DB 0x3C, 0x42
+"
+    );
+    assert_eq!(
+        disasm::disassemble(DISASM_ROM, disasm::Syntax::Octo),
+        "  :call label_206                # 200: 2206
+  if v0 != 0x01 then             # 202: 3001
+: label_204
+  jump label_204                 # 204: 1204
+: label_206
+  i := label_20C                 # 206: A20C
+  sprite v0 v1 2                 # 208: D012
+  return                         # 20A: 00EE
+: label_20C
+  0x3C 0x42                      # 20C: 3C42
+"
+    );
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        :const SPEED 3
+        :alias counter v5
+        :macro wait-key register { register := key }
+
+        : main
+            counter := 0
+            loop
+                counter += SPEED
+                if counter == 9 then counter := 1
+                while counter != 0x20
+                if v0 key begin
+                    draw
+                else
+                    i := long data
+                end
+            again
+            wait-key v2
+            jump main
+
+        : draw
+            i := data
+            sprite v0 v1 2
+            ;
+
+        : data
+            0x3C :byte 0b01000010 -1
+    ";
+
+    assert_eq!(
+        asm::assemble(source),
+        Ok(vec![
+            0x65, 0x00, // 200: counter := 0
+            0x75, 0x03, // 202: loop, counter += SPEED
+            0x45, 0x09, // 204: if counter == 9 then
+            0x65, 0x01, // 206: counter := 1
+            0x45, 0x20, // 208: while counter != 0x20
+            0x12, 0x1A, // 20A: to after again
+            0xE0, 0x9E, // 20C: if v0 key begin
+            0x12, 0x14, // 20E: to else
+            0x22, 0x1E, // 210: draw
+            0x12, 0x18, // 212: else, to end
+            0xF0, 0x00, 0x02, 0x24, // 214: i := long data
+            0x12, 0x02, // 218: again
+            0xF2, 0x0A, // 21A: wait-key v2
+            0x12, 0x00, // 21C: jump main
+            0xA2, 0x24, // 21E: draw
+            0xD0, 0x12, // 220
+            0x00, 0xEE, // 222
+            0x3C, 0x42, 0xFF, // 224: data
+        ])
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    let error = |source| asm::assemble(source).unwrap_err();
+
+    assert_eq!(
+        error("clear\n  v0 := 256"),
+        asm::AssembleError {
+            line: 2,
+            column: 9,
+            message: "256 does not fit in a byte".to_string(),
+        }
+    );
+    assert_eq!(
+        (error("jump nowhere").line, error("jump nowhere").column),
+        (1, 6)
+    );
+    assert_eq!(
+        error("loop clear").message,
+        "`loop` is never closed by `again`"
+    );
+    assert_eq!(error("if v0 > v1 then clear").column, 7);
+    assert_eq!(error(": a : a").column, 7);
+    assert_eq!(
+        error(":macro m { m } m").message,
+        "too many macro expansions, does a macro use itself?"
+    );
+    assert_eq!(error(": main v0 := --2147483648").column, 14);
+    assert_eq!(error(": main v0 := -0x-80000000").column, 14);
+
+    // Nothing can follow a program that fills memory, not even a label
+    let full = "0 ".repeat(0xFE00);
+    for source in [
+        format!("{}: end", full),
+        format!("{}loop again", full),
+        format!("{}if v0 == 0 begin end", &full[4 * 2..]),
+    ] {
+        assert_eq!(
+            asm::assemble(&source).unwrap_err().message,
+            "program does not fit in memory"
+        );
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_disassembly_reassembles(rom in proptest::collection::vec(any::<u8>(), 0..64)) {
+        let source = disasm::disassemble(&rom, disasm::Syntax::Octo);
+        prop_assert_eq!(asm::assemble(&source), Ok(rom));
+    }
+}
+
+fn debugger_with_program(program: &[u8]) -> Debugger {
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(program).unwrap();
+    Debugger::new(vm)
+}
+
+#[test]
+fn test_debugger_breakpoints_and_stepping() {
+    let mut debugger = debugger_with_program(&[
+        0x22, 0x08, // 200: call 208
+        0x60, 0x01, // 202: v0 := 1
+        0x12, 0x04, // 204: jump 204
+        0x00, 0x00, // 206
+        0x61, 0x02, // 208: v1 := 2
+        0x22, 0x0E, // 20A: call 20E
+        0x00, 0xEE, // 20C: return
+        0x62, 0x03, // 20E: v2 := 3
+        0x00, 0xEE, // 210: return
+    ]);
+
+    debugger.add_breakpoint(0x20E);
+    assert_eq!(debugger.run(100), StopReason::Breakpoint { pc: 0x20E });
+    // Running again steps off the breakpoint rather than stopping on it
+    assert_eq!(debugger.step_out(100), StopReason::Stepped);
+    assert_eq!(debugger.vm().pc(), 0x20C);
+    assert_eq!(debugger.step_out(100), StopReason::Stepped);
+    assert_eq!(debugger.vm().pc(), 0x202);
+
+    let mut debugger = debugger_with_program(&[
+        0x22, 0x08, 0x60, 0x01, 0x12, 0x04, 0x00, 0x00, 0x61, 0x02, 0x00, 0xEE,
+    ]);
+    assert_eq!(debugger.step_over(100), StopReason::Stepped);
+    assert_eq!(debugger.vm().pc(), 0x202);
+    assert_eq!(debugger.vm().registers()[1], 2);
+    assert_eq!(debugger.step(), StopReason::Stepped);
+    assert_eq!(debugger.vm().registers()[0], 1);
+    assert_eq!(debugger.run(10), StopReason::InstructionLimit);
+}
+
+#[test]
+fn test_debugger_watchpoints() {
+    let mut debugger = debugger_with_program(&[
+        0xA3, 0x00, // 200: i := 300
+        0x60, 0x07, // 202: v0 := 7
+        0xF1, 0x55, // 204: save v1
+        0xA3, 0x00, // 206: i := 300
+        0xD0, 0x02, // 208: sprite v0 v0 2
+        0x70, 0x01, // 20A: v0 += 1
+        0x12, 0x0A, // 20C: jump 20A
+    ]);
+
+    debugger.add_watchpoint(0x301, WatchKind::ReadWrite);
+    assert_eq!(
+        debugger.run(100),
+        StopReason::MemoryWrite {
+            pc: 0x204,
+            address: 0x301
+        }
+    );
+    assert_eq!(
+        debugger.run(100),
+        StopReason::MemoryRead {
+            pc: 0x208,
+            address: 0x301
+        }
+    );
+
+    debugger.watch_register(0);
+    assert_eq!(
+        debugger.run(100),
+        StopReason::RegisterChanged {
+            pc: 0x20A,
+            register: 0,
+            old: 7,
+            new: 8
+        }
+    );
+}
+
+#[test]
+fn test_inspection_api() {
+    let mut vm = xo_chip_vm(&[0x22, 0x04, 0x00, 0x00, 0x60, 0x2A]);
+    vm.execute_instruction().unwrap();
+    vm.execute_instruction().unwrap();
+
+    assert_eq!(vm.pc(), 0x206);
+    assert_eq!(vm.stack(), [0x200]);
+    assert_eq!(vm.stack_pointer(), 1);
+    assert_eq!(vm.registers()[0], 0x2A);
+    assert_eq!(vm.memory().len(), 0x10000);
+    assert_eq!(vm.memory()[0x204], 0x60);
+
+    vm.set_register(0x1F, 7);
+    vm.set_index(0x300);
+    vm.set_timers(Timers { delay: 3, sound: 4 });
+    vm.set_stack(&[0x210, 0x220]);
+    vm.set_pc(0x400);
+    vm.memory_mut()[0x400] = 0x00;
+    vm.memory_mut()[0x401] = 0xEE;
+
+    assert_eq!(vm.registers()[0xF], 7);
+    assert_eq!(vm.index(), 0x300);
+    assert_eq!(vm.timers(), Timers { delay: 3, sound: 4 });
+    vm.execute_instruction().unwrap();
+    assert_eq!(vm.pc(), 0x222);
+    assert_eq!(vm.stack(), [0x210]);
+}
+
+#[test]
+fn test_trace_formats() {
+    // 6005: V0 = 5, 2206: call 206, A321: I = 0x321, F055: store V0, F015: DT = V0
+    let mut vm = vm_with_program(
+        Quirks::default(),
+        &[
+            0x60, 0x05, 0x22, 0x06, 0x00, 0x00, 0xA3, 0x21, 0xF0, 0x55, 0xF0, 0x15,
+        ],
+    );
+    let mut tracer = trace::Tracer::new();
+    for _ in 0..5 {
+        tracer.step(&mut vm).unwrap();
+    }
+
+    let mut text = Vec::new();
+    tracer.write(trace::TraceFormat::Text, &mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(
+        text.lines().collect::<Vec<_>>(),
+        [
+            "PC:0200 OP:6005 V:05000000000000000000000000000000 I:0000 DT:00 ST:00 S:- W:- LD V0, 0x05",
+            "PC:0202 OP:2206 V:05000000000000000000000000000000 I:0000 DT:00 ST:00 S:0202 W:- CALL 0x206",
+            "PC:0206 OP:A321 V:05000000000000000000000000000000 I:0321 DT:00 ST:00 S:0202 W:- LD I, 0x321",
+            "PC:0208 OP:F055 V:05000000000000000000000000000000 I:0322 DT:00 ST:00 S:0202 W:0321=05 LD [I], V0",
+            "PC:020A OP:F015 V:05000000000000000000000000000000 I:0322 DT:05 ST:00 S:0202 W:- LD DT, V0",
+        ]
+    );
+    assert_eq!(
+        trace::read_trace(text.as_bytes()).as_deref(),
+        Ok(tracer.entries())
+    );
+
+    let mut binary = Vec::new();
+    tracer
+        .write(trace::TraceFormat::Binary, &mut binary)
+        .unwrap();
+    assert_eq!(binary.len(), 6 + 5 * 26 + 4 * 2 + 3);
+    assert_eq!(trace::read_trace(&binary).as_deref(), Ok(tracer.entries()));
+
+    // Version 1 records and text lines without a stack or writes still read
+    let mut first = tracer.entries()[1].clone();
+    let mut version_1 = b"C8TR\x01\x00".to_vec();
+    version_1.extend_from_slice(&binary[6 + 26..6 + 26 + 24]);
+    first.stack.clear();
+    assert_eq!(trace::read_trace(&version_1), Ok(vec![first.clone()]));
+    assert_eq!(
+        trace::read_trace(
+            b"PC:0202 OP:2206 V:05000000000000000000000000000000 I:0000 DT:00 ST:00 CALL 0x206\n"
+        ),
+        Ok(vec![first])
+    );
+
+    assert_eq!(
+        trace::read_trace(&binary[..binary.len() - 1]),
+        Err(trace::TraceError::Truncated)
+    );
+    assert_eq!(
+        trace::read_trace(b"PC:0200 OP:6005\n"),
+        Err(trace::TraceError::BadLine(1))
+    );
+}
+
+#[test]
+fn test_divergence() {
+    let mut left = vm_with_program(
+        Quirks::default(),
+        &[0x60, 0x05, 0x61, 0x01, 0xA3, 0x00, 0xF1, 0x55],
+    );
+    let mut right = vm_with_program(
+        Quirks::default(),
+        &[0x60, 0x05, 0x61, 0x02, 0xA3, 0x00, 0xF1, 0x55],
+    );
+    let (mut left_trace, mut right_trace) = (trace::Tracer::new(), trace::Tracer::new());
+    let (mut left_states, mut right_states) = (Vec::new(), Vec::new());
+    for _ in 0..4 {
+        left_trace.step(&mut left).unwrap();
+        right_trace.step(&mut right).unwrap();
+        left_states.extend(left.save_state());
+        right_states.extend(right.save_state());
+    }
+
+    let (left_trace, right_trace) = (left_trace.entries(), right_trace.entries());
+    assert_eq!(divergence::first_divergence(left_trace, left_trace), None);
+    assert_eq!(
+        divergence::first_divergence(left_trace, right_trace),
+        Some(1)
+    );
+    assert_eq!(
+        divergence::first_divergence(&left_trace[..2], &left_trace[..1]),
+        Some(1)
+    );
+    assert!(divergence::compare_entries(&left_trace[1], &right_trace[1])
+        .contains("V1       01                       02 <\n"));
+    assert!(divergence::compare_entries(&left_trace[3], &right_trace[3])
+        .contains("writes   [0300]=05 [0301]=01      [0300]=05 [0301]=02 <\n"));
+
+    let left_states = divergence::split_states(&left_states).unwrap();
+    let right_states = divergence::split_states(&right_states).unwrap();
+    assert_eq!(left_states.len(), 4);
+    // The ROMs differ, so the states do from the first one on
+    assert_eq!(
+        divergence::first_divergence(&left_states, &right_states),
+        Some(0)
+    );
+    let report = divergence::compare_vms(&left, &right);
+    assert!(report.contains("[0301]   01                       02 <\n"));
+    assert!(report.contains("PC       0208                     0208\n"));
+}
+
+#[test]
+fn test_sha1() {
+    let hex = |data: &[u8]| -> String {
+        sha1::sha1(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    };
+    assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    // 56 bytes, so the length spills into a second padding block
+    assert_eq!(
+        hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+    // The rest of the NIST vectors
+    assert_eq!(
+        hex(b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"),
+        "a49b2446a02c645bf419f995b67091253a04a259"
+    );
+    assert_eq!(
+        hex(&vec![b'a'; 1_000_000]),
+        "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+    );
+    assert_eq!(
+        hex(&[b'a'; 1000]),
+        "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+    );
+}
+
+#[test]
+fn test_json() {
+    use json::Value;
+
+    let value =
+        json::parse(r#" {"a": [1, -2.5e1, true, null], "b\n\u00e9\ud83d\ude00": {}} "#).unwrap();
+    assert_eq!(
+        value,
+        Value::Object(vec![
+            (
+                "a".to_owned(),
+                Value::Array(vec![
+                    Value::Number(1.0),
+                    Value::Number(-25.0),
+                    Value::Bool(true),
+                    Value::Null
+                ])
+            ),
+            ("b\n\u{e9}\u{1F600}".to_owned(), Value::Object(vec![])),
+        ])
+    );
+    assert_eq!(
+        value.get("a").and_then(|a| a.as_array()).map(|a| a.len()),
+        Some(4)
+    );
+
+    assert_eq!(json::parse("[1, 2"), Err(5));
+    assert_eq!(json::parse("{\"a\" 1}"), Err(5));
+    assert_eq!(json::parse("[] x"), Err(3));
+    assert_eq!(json::parse("\"\\ud83d\""), Err(7));
+
+    // Nesting is limited instead of overflowing the stack
+    let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(json::parse(&nested(json::MAX_DEPTH)).is_ok());
+    assert_eq!(
+        json::parse(&nested(json::MAX_DEPTH + 1)),
+        Err(json::MAX_DEPTH)
+    );
+    assert_eq!(
+        database::Database::from_json(&"[".repeat(1_000_000)).unwrap_err(),
+        database::DatabaseError::Syntax(json::MAX_DEPTH)
+    );
+}
+
+const TEST_DATABASE: &str = r##"[
+  {
+    "title": "Game",
+    "authors": ["Someone", "Someone Else"],
+    "roms": {
+      "a9993e364706816aba3e25717850c26c9cd0d89d": {
+        "platforms": ["megachip8", "superchip", "xochip"],
+        "quirkyPlatforms": {
+          "superchip": {"memoryLeaveIUnchanged": false, "memoryIncrementByX": true, "wrap": true, "vblank": true}
+        },
+        "tickrate": 30,
+        "startAddress": 1536,
+        "keys": {"up": 5, "a": 10},
+        "colors": {"pixels": ["#000000", "#FF8000"], "buzzer": "#FFAA00"}
+      },
+      "0000000000000000000000000000000000000000": {
+        "platforms": ["megachip8"]
+      }
+    }
+  },
+  {"title": "No ROMs"}
+]"##;
+
+#[test]
+fn test_database() {
+    let database = database::Database::from_json(TEST_DATABASE).unwrap();
+    assert_eq!(database.len(), 1);
+    assert_eq!(database.get(&[0; 20]), None);
+
+    let info = database.lookup(b"abc").unwrap();
+    assert_eq!(
+        *info,
+        database::RomInfo {
+            title: "Game".to_owned(),
+            authors: vec!["Someone".to_owned(), "Someone Else".to_owned()],
+            platform: "superchip".to_owned(),
+            instruction_set: InstructionSet::SuperChip,
+            quirks: Quirks {
+                load_store_index: IndexIncrement::X,
+                clip_sprites: false,
+                display_wait: true,
+                ..Quirks::SUPER_CHIP
+            },
+            tick_rate: Some(30),
+            start_address: 0x600,
+            keys: vec![("up".to_owned(), 5), ("a".to_owned(), 10)],
+            colors: vec![[0, 0, 0], [0xFF, 0x80, 0]],
+        }
+    );
+
+    assert_eq!(
+        database::Database::from_json("{}").unwrap_err(),
+        database::DatabaseError::InvalidValue("program list")
+    );
+    assert_eq!(
+        database::Database::from_json(&TEST_DATABASE.replace("\"up\": 5", "\"up\": 16"))
+            .unwrap_err(),
+        database::DatabaseError::InvalidValue("key")
+    );
+    assert_eq!(
+        database::Database::from_json("[").unwrap_err(),
+        database::DatabaseError::Syntax(1)
+    );
+}
+
+#[test]
+fn test_load_rom_with_database() {
+    let database = database::Database::from_json(TEST_DATABASE).unwrap();
+
+    let mut vm = VirtualMachine::new();
+    let info = vm.load_rom_with_database(b"abc", &database).unwrap();
+    assert_eq!(info.map(|info| info.title.as_str()), Some("Game"));
+    assert_eq!(vm.instruction_set(), InstructionSet::SuperChip);
+    assert_eq!(vm.quirks(), info.unwrap().quirks);
+    assert_eq!(vm.pc(), 0x600);
+    assert_eq!(vm.memory()[0x600..0x603], *b"abc");
+
+    // Unknown ROMs keep the current settings
+    assert_eq!(vm.load_rom_with_database(b"abd", &database), Ok(None));
+    assert_eq!(vm.instruction_set(), InstructionSet::SuperChip);
+    assert_eq!(vm.pc(), 0x200);
+
+    let builtin = database::Database::builtin();
+    let suite = include_bytes!("../../assets/chip8-test-suite.ch8");
+    let info = builtin.lookup(suite).unwrap();
+    assert_eq!(info.title, "CHIP-8 test suite");
+    assert_eq!(info.platform, "modernChip8");
+    assert_eq!(
+        (info.instruction_set, info.quirks),
+        (InstructionSet::Chip8, Quirks::default())
+    );
+    assert_eq!(info.tick_rate, None);
+}
+
+#[test]
+fn test_platforms() {
+    for platform in Platform::ALL {
+        let vm = VirtualMachine::with_platform(platform);
+        assert_eq!(vm.platform(), Some(platform));
+        assert_eq!(vm.instruction_set(), platform.instruction_set());
+        assert_eq!(vm.quirks(), platform.quirks());
+
+        let mut loaded = VirtualMachine::new();
+        loaded.load_state(&vm.save_state()).unwrap();
+        assert_eq!(loaded.platform(), Some(platform));
+    }
+    assert_eq!(VirtualMachine::new().platform(), None);
+
+    // 2200: call itself until the VIP's 12 entry stack runs out
+    let mut vm = VirtualMachine::with_platform(Platform::CosmacVip);
+    vm.load_rom(&[0x22, 0x00]).unwrap();
+    for _ in 0..12 {
+        vm.execute_instruction().unwrap();
+    }
+    assert_eq!(
+        vm.execute_instruction(),
+        Err(VmError::StackOverflow {
+            pc: 0x200,
+            opcode: 0x2200
+        })
+    );
+    // The VIP's 1 has a flat foot
+    assert_eq!(vm.memory()[5..10], [0x60, 0x20, 0x20, 0x20, 0x70]);
+
+    // Switching keeps the program and the calls in progress
+    vm.set_platform(Platform::XoChip);
+    assert_eq!(vm.memory()[0x200..0x202], [0x22, 0x00]);
+    assert_eq!(vm.stack().len(), 12);
+    assert_eq!(vm.memory()[5..10], [0x20, 0x60, 0x20, 0x20, 0x70]);
+    for _ in 0..4 {
+        vm.execute_instruction().unwrap();
+    }
+    assert!(vm.execute_instruction().is_err());
+}
+
+#[derive(Debug, PartialEq)]
+enum HookEvent {
+    Instruction(u16, u16),
+    Read(u16, u8),
+    Write(u16, u8),
+    Draw(u8, u8, u8, bool),
+    SoundStart,
+    SoundStop,
+}
+
+impl Hooks for Vec<HookEvent> {
+    fn on_instruction(&mut self, pc: u16, opcode: u16) {
+        self.push(HookEvent::Instruction(pc, opcode));
+    }
+
+    fn on_mem_read(&mut self, address: u16, value: u8) {
+        self.push(HookEvent::Read(address, value));
+    }
+
+    fn on_mem_write(&mut self, address: u16, value: u8) {
+        self.push(HookEvent::Write(address, value));
+    }
+
+    fn on_draw(&mut self, x: u8, y: u8, n: u8, collided: bool) {
+        self.push(HookEvent::Draw(x, y, n, collided));
+    }
+
+    fn on_sound_start(&mut self) {
+        self.push(HookEvent::SoundStart);
+    }
+
+    fn on_sound_stop(&mut self) {
+        self.push(HookEvent::SoundStop);
+    }
+}
+
+#[test]
+fn test_hooks() {
+    use HookEvent::*;
+
+    // 6102: V1 = 2, F118: sound for V1 frames, A300: I = 0x300, F133: BCD of V1,
+    // F165: V0, V1 = [I..], A000: I = font 0, D011 twice: draw and erase it
+    let program = [
+        0x61, 0x02, 0xF1, 0x18, 0xA3, 0x00, 0xF1, 0x33, 0xF1, 0x65, 0xA0, 0x00, 0xD0, 0x11, 0xD0,
+        0x11,
+    ];
+    let mut vm = vm_with_program(Quirks::default(), &program);
+    let mut events = Vec::new();
+    for _ in 0..program.len() / 2 {
+        vm.execute_instruction_with(&mut events).unwrap();
+    }
+    for _ in 0..3 {
+        vm.decrement_timers_with(&mut events);
+    }
+
+    assert_eq!(
+        events,
+        [
+            Instruction(0x200, 0x6102),
+            Instruction(0x202, 0xF118),
+            SoundStart,
+            Instruction(0x204, 0xA300),
+            Instruction(0x206, 0xF133),
+            Write(0x300, 0),
+            Write(0x301, 0),
+            Write(0x302, 2),
+            Instruction(0x208, 0xF165),
+            Read(0x300, 0),
+            Read(0x301, 0),
+            Instruction(0x20A, 0xA000),
+            Instruction(0x20C, 0xD011),
+            Read(0x000, 0xF0),
+            Draw(0, 0, 1, false),
+            Instruction(0x20E, 0xD011),
+            Read(0x000, 0xF0),
+            Draw(0, 0, 1, true),
+            SoundStop,
+        ]
+    );
 }
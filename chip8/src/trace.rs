@@ -0,0 +1,382 @@
+//! Execution traces, one entry per executed instruction holding the state
+//! right after it ran.
+//!
+//! The text format is a line per instruction, fields separated by one space:
+//!
+//! ```text
+//! PC:0206 OP:F155 V:05020000000000000000000000000000 I:0302 DT:00 ST:00 S:0202 W:0300=05,0301=02 LD [I], V1
+//! ```
+//!
+//! V0 to VF are written as one run of hex digits. S lists the return
+//! addresses of the calls in progress, innermost last, and W the bytes the
+//! instruction wrote to memory, each a comma separated list or `-` when
+//! empty. Traces from tools that leave S and W out read as if both were
+//! empty. Everything after them is the classic mnemonic, which is for people
+//! and ignored when a trace is read, so traces from emulators that name
+//! instructions differently still compare.
+//!
+//! The binary format is the magic `C8TR` and a `u16` version, followed by a
+//! record per instruction: PC, opcode, V0 to VF, I, DT and ST in 24 bytes,
+//! then the stack as a count byte and the addresses, and the writes as a count
+//! byte and an address and value each. All integers are little endian.
+//! Version 1 records stop after ST.
+
+use std::fmt;
+use std::io;
+
+use crate::disasm::{self, Syntax};
+use crate::{decode, Hooks, RandomSource, StepOutcome, VirtualMachine, VmError};
+
+const MAGIC: &[u8; 4] = b"C8TR";
+pub const TRACE_VERSION: u16 = 2;
+// The registers and timers every version starts a record with
+const FIXED_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Return addresses of the calls in progress, innermost last
+    pub stack: Vec<u16>,
+    /// Bytes the instruction wrote to memory, as address and value
+    pub writes: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceError {
+    UnsupportedVersion(u16),
+    Truncated,
+    /// Line number, counting from 1, of a text line that could not be read
+    BadLine(usize),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported trace version {}", version)
+            }
+            TraceError::Truncated => write!(f, "Trace is truncated"),
+            TraceError::BadLine(line) => write!(f, "Trace line {} is malformed", line),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl TraceEntry {
+    fn after<R: RandomSource>(
+        pc: u16,
+        opcode: u16,
+        vm: &VirtualMachine<R>,
+        writes: Vec<(u16, u8)>,
+    ) -> Self {
+        let timers = vm.timers();
+        TraceEntry {
+            pc,
+            opcode,
+            registers: *vm.registers(),
+            index: vm.index(),
+            delay_timer: timers.delay,
+            sound_timer: timers.sound,
+            stack: vm.stack().to_vec(),
+            writes,
+        }
+    }
+
+    /// The text format line, without a line break
+    pub fn to_text(&self) -> String {
+        let registers: String = self
+            .registers
+            .iter()
+            .map(|register| format!("{:02X}", register))
+            .collect();
+
+        format!(
+            "PC:{:04X} OP:{:04X} V:{} I:{:04X} DT:{:02X} ST:{:02X} S:{} W:{} {}",
+            self.pc,
+            self.opcode,
+            registers,
+            self.index,
+            self.delay_timer,
+            self.sound_timer,
+            text_list(self.stack.iter().map(|address| format!("{:04X}", address))),
+            text_list(
+                self.writes
+                    .iter()
+                    .map(|(address, value)| format!("{:04X}={:02X}", address, value))
+            ),
+            self.mnemonic()
+        )
+    }
+
+    pub fn mnemonic(&self) -> String {
+        decode(self.opcode).map_or_else(
+            |_| "???".to_string(),
+            |instruction| disasm::mnemonic(instruction, Syntax::Classic),
+        )
+    }
+
+    fn from_text(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let mut field = |name: &str, digits: usize| {
+            let value = fields.next()?.strip_prefix(name)?;
+            (value.len() == digits).then_some(value)
+        };
+        let hex = |value: &str| u16::from_str_radix(value, 16).ok();
+
+        let pc = hex(field("PC:", 4)?)?;
+        let opcode = hex(field("OP:", 4)?)?;
+        let mut registers = [0; 16];
+        let register_digits = field("V:", 32)?;
+        for (index, register) in registers.iter_mut().enumerate() {
+            *register =
+                u8::from_str_radix(register_digits.get(index * 2..index * 2 + 2)?, 16).ok()?;
+        }
+
+        let index = hex(field("I:", 4)?)?;
+        let delay_timer = hex(field("DT:", 2)?)? as u8;
+        let sound_timer = hex(field("ST:", 2)?)? as u8;
+
+        let mut fields = fields.peekable();
+        // Missing lists read as empty
+        let mut list = |name: &str| match fields
+            .peek()
+            .copied()
+            .and_then(|field| field.strip_prefix(name))
+        {
+            Some(list) => {
+                fields.next();
+                list
+            }
+            None => "-",
+        };
+        let address = |text: &str| (text.len() == 4).then(|| hex(text)).flatten();
+        let stack = parse_list(list("S:"), address)?;
+        let writes = parse_list(list("W:"), |write| {
+            let (address_digits, value) = write.split_once('=')?;
+            let value = (value.len() == 2).then(|| hex(value)).flatten()?;
+            Some((address(address_digits)?, value as u8))
+        })?;
+
+        Some(TraceEntry {
+            pc,
+            opcode,
+            registers,
+            index,
+            delay_timer,
+            sound_timer,
+            stack,
+            writes,
+        })
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) {
+        out.extend(self.pc.to_le_bytes());
+        out.extend(self.opcode.to_le_bytes());
+        out.extend_from_slice(&self.registers);
+        out.extend(self.index.to_le_bytes());
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.push(self.stack.len() as u8);
+        for address in &self.stack {
+            out.extend(address.to_le_bytes());
+        }
+        out.push(self.writes.len() as u8);
+        for (address, value) in &self.writes {
+            out.extend(address.to_le_bytes());
+            out.push(*value);
+        }
+    }
+
+    /// Reads a record from the start of `records`, returning it and the rest
+    fn from_binary(records: &[u8], version: u16) -> Result<(Self, &[u8]), TraceError> {
+        let mut records = records;
+        let record = take(&mut records, FIXED_LEN)?;
+        let u16_at =
+            |bytes: &[u8], offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&record[4..20]);
+
+        let mut entry = TraceEntry {
+            pc: u16_at(record, 0),
+            opcode: u16_at(record, 2),
+            registers,
+            index: u16_at(record, 20),
+            delay_timer: record[22],
+            sound_timer: record[23],
+            stack: Vec::new(),
+            writes: Vec::new(),
+        };
+        if version >= 2 {
+            let depth = take(&mut records, 1)?[0] as usize;
+            let stack = take(&mut records, depth * 2)?;
+            entry.stack = stack
+                .chunks_exact(2)
+                .map(|bytes| u16_at(bytes, 0))
+                .collect();
+            let count = take(&mut records, 1)?[0] as usize;
+            let writes = take(&mut records, count * 3)?;
+            entry.writes = writes
+                .chunks_exact(3)
+                .map(|bytes| (u16_at(bytes, 0), bytes[2]))
+                .collect();
+        }
+
+        Ok((entry, records))
+    }
+}
+
+/// Splits `len` bytes off the front of `records`
+fn take<'a>(records: &mut &'a [u8], len: usize) -> Result<&'a [u8], TraceError> {
+    if records.len() < len {
+        return Err(TraceError::Truncated);
+    }
+    let (taken, rest) = records.split_at(len);
+    *records = rest;
+    Ok(taken)
+}
+
+/// A comma separated list, `-` when empty
+fn text_list(items: impl Iterator<Item = String>) -> String {
+    let list = items.collect::<Vec<_>>().join(",");
+    if list.is_empty() {
+        "-".to_string()
+    } else {
+        list
+    }
+}
+
+fn parse_list<T>(text: &str, item: impl Fn(&str) -> Option<T>) -> Option<Vec<T>> {
+    if text == "-" {
+        return Some(Vec::new());
+    }
+    text.split(',').map(item).collect()
+}
+
+// Collects what an instruction writes to memory
+#[derive(Default)]
+struct Writes(Vec<(u16, u8)>);
+
+impl Hooks for Writes {
+    fn on_mem_write(&mut self, address: u16, value: u8) {
+        self.0.push((address, value));
+    }
+}
+
+/// Records the instructions a VM executes through it
+#[derive(Debug, Default)]
+pub struct Tracer {
+    entries: Vec<TraceEntry>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executes one instruction, recording it if it ran
+    pub fn step<R: RandomSource>(
+        &mut self,
+        vm: &mut VirtualMachine<R>,
+    ) -> Result<StepOutcome, VmError> {
+        let pc = vm.pc();
+        // Fetched up front, the instruction may overwrite itself
+        let opcode = vm.fetch_opcode();
+        let mut writes = Writes::default();
+        let outcome = vm.execute_instruction_with(&mut writes)?;
+
+        if let (StepOutcome::Executed, Ok(opcode)) = (outcome, opcode) {
+            self.entries
+                .push(TraceEntry::after(pc, opcode, vm, writes.0));
+        }
+
+        Ok(outcome)
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn write(&self, format: TraceFormat, out: &mut impl io::Write) -> io::Result<()> {
+        write_trace(&self.entries, format, out)
+    }
+}
+
+pub fn write_trace(
+    entries: &[TraceEntry],
+    format: TraceFormat,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    match format {
+        TraceFormat::Text => {
+            for entry in entries {
+                writeln!(out, "{}", entry.to_text())?;
+            }
+        }
+        TraceFormat::Binary => {
+            let mut bytes = Vec::with_capacity(MAGIC.len() + 2 + entries.len() * (FIXED_LEN + 2));
+            bytes.extend_from_slice(MAGIC);
+            bytes.extend(TRACE_VERSION.to_le_bytes());
+            for entry in entries {
+                entry.write_binary(&mut bytes);
+            }
+            out.write_all(&bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a trace in either format, telling them apart by the binary magic
+pub fn read_trace(data: &[u8]) -> Result<Vec<TraceEntry>, TraceError> {
+    let Some(binary) = data.strip_prefix(MAGIC) else {
+        let text = std::str::from_utf8(data).map_err(|error| {
+            let line = data[..error.valid_up_to()]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count();
+            TraceError::BadLine(line + 1)
+        })?;
+
+        return text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                TraceEntry::from_text(line.trim_end()).ok_or(TraceError::BadLine(number + 1))
+            })
+            .collect();
+    };
+
+    let (version, records) = match binary {
+        [low, high, records @ ..] => (u16::from_le_bytes([*low, *high]), records),
+        _ => return Err(TraceError::Truncated),
+    };
+    if !(1..=TRACE_VERSION).contains(&version) {
+        return Err(TraceError::UnsupportedVersion(version));
+    }
+
+    let mut entries = Vec::new();
+    let mut records = records;
+    while !records.is_empty() {
+        let (entry, rest) = TraceEntry::from_binary(records, version)?;
+        entries.push(entry);
+        records = rest;
+    }
+    Ok(entries)
+}
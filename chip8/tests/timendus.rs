@@ -0,0 +1,186 @@
+//! Boots each part of Timendus' CHIP-8 test suite and compares the screen
+//! after a fixed number of frames with the images in `tests/golden`.
+//!
+//! Set `UPDATE_GOLDEN=1` to rewrite the images after a deliberate change.
+
+#![cfg(feature = "std")]
+
+use std::path::PathBuf;
+
+use chip8::{InstructionSet, Platform, Quirks, SeededRandom, StepOutcome, VirtualMachine};
+
+const SUITE: &[u8] = include_bytes!("../../assets/chip8-test-suite.ch8");
+
+// The suite skips its menu when these bytes are set before it starts
+const SUB_TEST_ADDRESS: usize = 0x1FF;
+const PLATFORM_ADDRESS: usize = 0x1FE;
+
+const IBM_LOGO: u8 = 1;
+const CORAX_PLUS: u8 = 2;
+const FLAGS: u8 = 3;
+const QUIRKS: u8 = 4;
+const KEYPAD: u8 = 5;
+
+const INSTRUCTIONS_PER_FRAME: u32 = 20;
+
+struct Run {
+    sub_test: u8,
+    platform: u8,
+    instruction_set: InstructionSet,
+    quirks: Quirks,
+    frames: u32,
+    /// Key held down and the frames it is held for
+    press: Option<(u8, std::ops::Range<u32>)>,
+}
+
+impl Run {
+    fn new(sub_test: u8) -> Run {
+        Run {
+            sub_test,
+            platform: 0,
+            instruction_set: InstructionSet::Chip8,
+            quirks: Quirks::default(),
+            frames: 600,
+            press: None,
+        }
+    }
+
+    fn screen(&self) -> String {
+        let mut vm = VirtualMachine::with_random_source(
+            self.instruction_set,
+            self.quirks,
+            SeededRandom::default(),
+        );
+        vm.load_rom(SUITE).unwrap();
+        vm.memory_mut()[SUB_TEST_ADDRESS] = self.sub_test;
+        vm.memory_mut()[PLATFORM_ADDRESS] = self.platform;
+
+        'frames: for frame in 0..self.frames {
+            for key in 0..16 {
+                let is_down = matches!(&self.press, Some((k, frames)) if *k == key && frames.contains(&frame));
+                if vm.blocked_on_key_press && vm.key_state[key as usize] && !is_down {
+                    vm.complete_fx0a(key);
+                }
+                vm.key_state[key as usize] = is_down;
+            }
+
+            for _ in 0..INSTRUCTIONS_PER_FRAME {
+                match vm.execute_instruction() {
+                    Ok(StepOutcome::Executed) => {}
+                    Ok(StepOutcome::Exited) => break 'frames,
+                    Ok(_) => break,
+                    Err(error) => panic!("{} in frame {}", error, frame),
+                }
+            }
+            vm.decrement_timers();
+        }
+
+        let mut text = String::new();
+        for row in vm.screen_rows() {
+            text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn check(&self, name: &str) {
+        let screen = self.screen();
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension("txt");
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &screen).unwrap();
+            return;
+        }
+
+        let golden = std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| panic!("could not read {}: {}", path.display(), error));
+        assert!(
+            screen == golden,
+            "screen differs from {}:\n{}",
+            path.display(),
+            screen
+        );
+    }
+}
+
+#[test]
+fn ibm_logo() {
+    Run::new(IBM_LOGO).check("ibm_logo");
+}
+
+#[test]
+fn corax_plus() {
+    Run::new(CORAX_PLUS).check("corax_plus");
+}
+
+#[test]
+fn flags() {
+    Run::new(FLAGS).check("flags");
+}
+
+/// The entry of the suite's platform menu that runs on `instruction_set`
+fn suite_platform(instruction_set: InstructionSet) -> u8 {
+    match instruction_set {
+        InstructionSet::Chip8 => 1,
+        InstructionSet::SuperChip => 2,
+        InstructionSet::XoChip => 3,
+    }
+}
+
+#[test]
+fn quirks() {
+    let mut profiles = vec![("default", InstructionSet::Chip8, Quirks::default())];
+    profiles.extend(Platform::ALL.into_iter().map(|platform| {
+        let name = match platform {
+            Platform::CosmacVip => "cosmac_vip",
+            Platform::Chip48 => "chip_48",
+            Platform::SuperChipLegacy => "super_chip_legacy",
+            Platform::SuperChipModern => "super_chip",
+            Platform::XoChip => "xo_chip",
+        };
+        (name, platform.instruction_set(), platform.quirks())
+    }));
+
+    for (name, instruction_set, quirks) in profiles {
+        Run {
+            platform: suite_platform(instruction_set),
+            instruction_set,
+            quirks,
+            ..Run::new(QUIRKS)
+        }
+        .check(&format!("quirks_{}", name));
+    }
+}
+
+#[test]
+fn keypad_ex9e() {
+    Run {
+        platform: 1,
+        press: Some((0x5, 300..600)),
+        ..Run::new(KEYPAD)
+    }
+    .check("keypad_ex9e");
+}
+
+#[test]
+fn keypad_exa1() {
+    Run {
+        platform: 2,
+        press: Some((0x5, 300..600)),
+        ..Run::new(KEYPAD)
+    }
+    .check("keypad_exa1");
+}
+
+#[test]
+fn keypad_fx0a() {
+    Run {
+        platform: 3,
+        press: Some((0x5, 30..40)),
+        ..Run::new(KEYPAD)
+    }
+    .check("keypad_fx0a");
+}